@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use anyhow::Result;
+use io::channel::{ActionKind, ContextActionMessage};
+use io::ActionsFileReader;
+
+/// Counts of the actions of one kind
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct KindStats {
+    pub actions: u64,
+    pub recorded: u64,
+    pub performed: u64,
+    /// Total duration in seconds
+    pub duration: f64,
+}
+
+impl KindStats {
+    fn add(&mut self, msg: &ContextActionMessage) {
+        self.actions += 1;
+        self.recorded += msg.record as u64;
+        self.performed += msg.perform as u64;
+        self.duration += msg.action.duration();
+    }
+}
+
+/// Block and action counts of an actions file, by kind and by `record` and `perform` flag
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub blocks: u64,
+    pub total: KindStats,
+    pub kinds: BTreeMap<ActionKind, KindStats>,
+}
+
+impl Stats {
+    pub fn add_block(&mut self, actions: &[ContextActionMessage]) {
+        self.blocks += 1;
+        for msg in actions {
+            self.total.add(msg);
+            self.kinds.entry(msg.action.kind()).or_default().add(msg);
+        }
+    }
+
+    pub fn collect(reader: ActionsFileReader) -> Result<Self> {
+        let mut stats = Stats::default();
+        for item in reader {
+            let (_, actions) = item?;
+            stats.add_block(&actions);
+        }
+        Ok(stats)
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<35}{}", "BLOCKS:", self.blocks)?;
+        writeln!(f, "{:<35}{}", "ACTIONS:", self.total.actions)?;
+        writeln!(f, "{:<35}{}", "RECORDED:", self.total.recorded)?;
+        writeln!(f, "{:<35}{}", "PERFORMED:", self.total.performed)?;
+        writeln!(f, "{:<35}{:.6}", "DURATION (s):", self.total.duration)?;
+        writeln!(f)?;
+        writeln!(f, "{:<20}{:>12}{:>12}{:>12}{:>16}", "KIND", "ACTIONS", "RECORDED", "PERFORMED", "DURATION (s)")?;
+        for (kind, stats) in &self.kinds {
+            writeln!(f, "{:<20}{:>12}{:>12}{:>12}{:>16.6}", kind.as_str(), stats.actions, stats.recorded, stats.performed, stats.duration)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use io::channel::ContextAction;
+
+    #[test]
+    fn test_flag_counts() {
+        let msg = |record: bool, perform: bool| ContextActionMessage {
+            action: ContextAction::Checkout { context_hash: vec![1; 32], start_time: 1.0, end_time: 1.5 },
+            record,
+            perform,
+        };
+        let mut stats = Stats::default();
+        stats.add_block(&[msg(true, true), msg(true, false)]);
+        stats.add_block(&[msg(false, true), ContextActionMessage::shutdown()]);
+
+        assert_eq!(stats.blocks, 2);
+        assert_eq!(stats.total, KindStats { actions: 4, recorded: 2, performed: 2, duration: 1.5 });
+        assert_eq!(stats.kinds[&ActionKind::Checkout].actions, 3);
+        assert!(stats.to_string().contains("checkout"));
+    }
+}
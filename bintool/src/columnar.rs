@@ -0,0 +1,174 @@
+use std::fs::File;
+use std::sync::Arc;
+
+use anyhow::Result;
+use arrow::array::{ArrayRef, BooleanBuilder, Float64Builder, StringBuilder, UInt32Builder, UInt64Builder};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use io::channel::ContextActionMessage;
+use io::ActionsFileReader;
+use parquet::arrow::ArrowWriter;
+
+/// Number of actions buffered before they are written out as one record batch
+const BATCH_ROWS: usize = 64 * 1024;
+
+fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("level", DataType::UInt32, false),
+        Field::new("block_hash", DataType::Utf8, false),
+        Field::new("kind", DataType::Utf8, false),
+        Field::new("key", DataType::Utf8, true),
+        Field::new("value_size", DataType::UInt64, true),
+        Field::new("start_time", DataType::Float64, true),
+        Field::new("end_time", DataType::Float64, true),
+        Field::new("duration", DataType::Float64, true),
+        Field::new("record", DataType::Boolean, false),
+        Field::new("perform", DataType::Boolean, false),
+    ]))
+}
+
+/// Accumulates one row per action, columns follow [`schema`]
+#[derive(Default)]
+struct BatchBuilder {
+    level: UInt32Builder,
+    block_hash: StringBuilder,
+    kind: StringBuilder,
+    key: StringBuilder,
+    value_size: UInt64Builder,
+    start_time: Float64Builder,
+    end_time: Float64Builder,
+    duration: Float64Builder,
+    record: BooleanBuilder,
+    perform: BooleanBuilder,
+    rows: usize,
+}
+
+impl BatchBuilder {
+    fn push(&mut self, level: u32, block_hash: &str, msg: &ContextActionMessage) {
+        let action = &msg.action;
+        self.level.append_value(level);
+        self.block_hash.append_value(block_hash);
+        self.kind.append_value(action.kind().as_str());
+        self.key.append_option(action.key().map(|key| key.join("/")));
+        self.value_size.append_option(action.value_size().map(|size| size as u64));
+        self.start_time.append_option(action.start_time());
+        self.end_time.append_option(action.end_time());
+        self.duration.append_option(action.start_time().map(|_| action.duration()));
+        self.record.append_value(msg.record);
+        self.perform.append_value(msg.perform);
+        self.rows += 1;
+    }
+
+    fn finish(&mut self, schema: &SchemaRef) -> Result<RecordBatch> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.level.finish()),
+            Arc::new(self.block_hash.finish()),
+            Arc::new(self.kind.finish()),
+            Arc::new(self.key.finish()),
+            Arc::new(self.value_size.finish()),
+            Arc::new(self.start_time.finish()),
+            Arc::new(self.end_time.finish()),
+            Arc::new(self.duration.finish()),
+            Arc::new(self.record.finish()),
+            Arc::new(self.perform.finish()),
+        ];
+        self.rows = 0;
+        Ok(RecordBatch::try_new(schema.clone(), columns)?)
+    }
+}
+
+/// Feeds every action of `reader` to `write` in batches of at most [`BATCH_ROWS`] rows,
+/// returns the number of rows written
+fn write_batches<F>(reader: ActionsFileReader, schema: &SchemaRef, mut write: F) -> Result<u64>
+    where F: FnMut(&RecordBatch) -> Result<()> {
+    let mut builder = BatchBuilder::default();
+    let mut rows = 0;
+    for item in reader {
+        let (block, actions) = item?;
+        let block_hash = block.block_hash.to_string();
+        for msg in &actions {
+            builder.push(block.block_level, &block_hash, msg);
+            if builder.rows >= BATCH_ROWS {
+                rows += builder.rows as u64;
+                write(&builder.finish(schema)?)?;
+            }
+        }
+    }
+    if builder.rows > 0 {
+        rows += builder.rows as u64;
+        write(&builder.finish(schema)?)?;
+    }
+    Ok(rows)
+}
+
+/// Exports one row per action of `reader` to a Parquet file
+pub fn export_parquet(reader: ActionsFileReader, out: File) -> Result<u64> {
+    let schema = schema();
+    let mut writer = ArrowWriter::try_new(out, schema.clone(), None)?;
+    let rows = write_batches(reader, &schema, |batch| Ok(writer.write(batch)?))?;
+    writer.close()?;
+    Ok(rows)
+}
+
+/// Exports one row per action of `reader` to an Arrow IPC file
+pub fn export_arrow(reader: ActionsFileReader, out: File) -> Result<u64> {
+    let schema = schema();
+    let mut writer = arrow::ipc::writer::FileWriter::try_new(out, &schema)?;
+    let rows = write_batches(reader, &schema, |batch| Ok(writer.write(batch)?))?;
+    writer.finish()?;
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Array, Float64Array, StringArray};
+    use io::channel::ContextAction;
+    use io::{ActionsFileWriter, Block};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn test_export_parquet() {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("actions.bin");
+        let mut writer = ActionsFileWriter::new(&bin).unwrap();
+        let actions = vec![
+            ContextActionMessage {
+                action: ContextAction::Checkout { context_hash: vec![1; 32], start_time: 1.0, end_time: 3.0 },
+                record: true,
+                perform: true,
+            },
+            ContextActionMessage {
+                action: ContextAction::Mem {
+                    context_hash: None,
+                    block_hash: None,
+                    operation_hash: None,
+                    tree_hash: vec![],
+                    start_time: 4.0,
+                    end_time: 4.5,
+                    key: vec!["data".to_string(), "votes".to_string()],
+                    value: true,
+                },
+                record: true,
+                perform: true,
+            },
+        ];
+        writer.update(Block::new(1, vec![1; 32], vec![0; 32]), actions).unwrap();
+
+        let path = dir.path().join("actions.parquet");
+        let rows = export_parquet(ActionsFileReader::new(&bin).unwrap(), File::create(&path).unwrap()).unwrap();
+        assert_eq!(rows, 2);
+
+        let batches: Vec<_> = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap()
+            .build().unwrap()
+            .collect::<std::result::Result<_, _>>().unwrap();
+        let batch = &batches[0];
+        let kind = batch.column(2).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(kind.value(1), "mem");
+        let key = batch.column(3).as_any().downcast_ref::<StringArray>().unwrap();
+        assert!(key.is_null(0));
+        assert_eq!(key.value(1), "data/votes");
+        let duration = batch.column(7).as_any().downcast_ref::<Float64Array>().unwrap();
+        assert_eq!(duration.value(0), 2.0);
+    }
+}
@@ -0,0 +1,300 @@
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use io::channel::ContextActionMessage;
+use io::hash::{ContextHash, OperationHash};
+use io::jsonl::block_lines;
+use io::{ActionFilter, ActionsFileReader};
+use rusqlite::params;
+
+/// Writes every action of `reader` to `out` as JSON Lines, returns the number of lines written
+pub fn export_jsonl<W: Write>(reader: ActionsFileReader, out: W) -> Result<u64> {
+    let mut out = BufWriter::new(out);
+    let mut count = 0;
+    for item in reader {
+        let (block, actions) = item?;
+        for line in block_lines(&block, &actions)? {
+            serde_json::to_writer(&mut out, &line)?;
+            out.write_all(b"\n")?;
+            count += 1;
+        }
+    }
+    out.flush()?;
+    Ok(count)
+}
+
+const SQLITE_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS blocks (
+    level INTEGER PRIMARY KEY,
+    hash TEXT NOT NULL,
+    predecessor TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS keys (
+    id INTEGER PRIMARY KEY,
+    path TEXT NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS actions (
+    id INTEGER PRIMARY KEY,
+    level INTEGER NOT NULL REFERENCES blocks(level),
+    position INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    key_id INTEGER REFERENCES keys(id),
+    operation_hash TEXT,
+    context_hash TEXT,
+    value_size INTEGER,
+    start_time REAL,
+    end_time REAL,
+    duration REAL,
+    record INTEGER NOT NULL,
+    perform INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS actions_level ON actions(level);
+CREATE INDEX IF NOT EXISTS actions_kind ON actions(kind);
+CREATE INDEX IF NOT EXISTS actions_key ON actions(key_id);
+";
+
+/// Number of blocks inserted per transaction
+const SQLITE_BATCH_BLOCKS: u32 = 1000;
+
+/// Exports the actions of `file` selected by `filter` into the SQLite database at `path`,
+/// returns the number of blocks exported
+///
+/// Keys are stored once in `keys`, joined with `/`; prefix queries such as
+/// `path GLOB 'data/contracts/index/*'` use its index. Reading starts after the last level
+/// already in the database, so an interrupted or repeated export resumes where it stopped.
+pub fn export_sqlite<P: AsRef<Path>, Q: AsRef<Path>>(file: P, filter: ActionFilter, path: Q) -> Result<u32> {
+    let mut conn = rusqlite::Connection::open(path)?;
+    conn.execute_batch(SQLITE_SCHEMA)?;
+    let last_level: Option<u32> = conn.query_row("SELECT MAX(level) FROM blocks", [], |row| row.get(0))?;
+    let filter = match last_level {
+        Some(last_level) => filter.from_level_at_least(last_level + 1),
+        None => filter,
+    };
+    let reader = ActionsFileReader::new(file)?.with_filter(filter);
+
+    let mut count = 0;
+    let mut tx = conn.transaction()?;
+    for item in reader {
+        let (block, actions) = item?;
+        tx.execute(
+            "INSERT INTO blocks (level, hash, predecessor) VALUES (?1, ?2, ?3)",
+            params![block.block_level, block.block_hash.to_string(), block.predecessor.to_string()],
+        )?;
+        for (position, msg) in actions.iter().enumerate() {
+            let action = &msg.action;
+            let key_id = match action.key() {
+                Some(key) => {
+                    let path = key.join("/");
+                    tx.prepare_cached("INSERT OR IGNORE INTO keys (path) VALUES (?1)")?.execute(params![path])?;
+                    Some(tx.prepare_cached("SELECT id FROM keys WHERE path = ?1")?.query_row(params![path], |row| row.get::<_, i64>(0))?)
+                }
+                None => None,
+            };
+            tx.execute(
+                "INSERT INTO actions (level, position, kind, key_id, operation_hash, context_hash, value_size, \
+                 start_time, end_time, duration, record, perform) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                params![
+                    block.block_level,
+                    position as i64,
+                    action.kind().to_string(),
+                    key_id,
+                    b58::<OperationHash>(action.operation_hash()),
+                    b58::<ContextHash>(action.context_hash()),
+                    action.value_size().map(|size| size as i64),
+                    action.start_time(),
+                    action.end_time(),
+                    action.start_time().map(|_| action.duration()),
+                    msg.record,
+                    msg.perform,
+                ],
+            )?;
+        }
+        count += 1;
+        if count % SQLITE_BATCH_BLOCKS == 0 {
+            tx.commit()?;
+            tx = conn.transaction()?;
+        }
+    }
+    tx.commit()?;
+    Ok(count)
+}
+
+/// Column of the CSV export
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsvColumn {
+    Level,
+    BlockHash,
+    Kind,
+    Key,
+    Duration,
+    StartTime,
+    EndTime,
+    ValueSize,
+    OperationHash,
+    Record,
+    Perform,
+}
+
+impl CsvColumn {
+    pub const ALL: [CsvColumn; 11] = [
+        CsvColumn::Level,
+        CsvColumn::BlockHash,
+        CsvColumn::Kind,
+        CsvColumn::Key,
+        CsvColumn::Duration,
+        CsvColumn::StartTime,
+        CsvColumn::EndTime,
+        CsvColumn::ValueSize,
+        CsvColumn::OperationHash,
+        CsvColumn::Record,
+        CsvColumn::Perform,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CsvColumn::Level => "level",
+            CsvColumn::BlockHash => "block_hash",
+            CsvColumn::Kind => "kind",
+            CsvColumn::Key => "key",
+            CsvColumn::Duration => "duration",
+            CsvColumn::StartTime => "start_time",
+            CsvColumn::EndTime => "end_time",
+            CsvColumn::ValueSize => "value_size",
+            CsvColumn::OperationHash => "operation_hash",
+            CsvColumn::Record => "record",
+            CsvColumn::Perform => "perform",
+        }
+    }
+
+    /// Formats the column for a message, empty if its action has no such field
+    fn value(&self, level: u32, block_hash: &str, msg: &ContextActionMessage) -> String {
+        let action = &msg.action;
+        match self {
+            CsvColumn::Level => level.to_string(),
+            CsvColumn::BlockHash => block_hash.to_string(),
+            CsvColumn::Kind => action.kind().to_string(),
+            CsvColumn::Key => action.key().map(|key| key.join("/")).unwrap_or_default(),
+            CsvColumn::Duration => action.start_time().map(|_| action.duration().to_string()).unwrap_or_default(),
+            CsvColumn::StartTime => action.start_time().map(|time| time.to_string()).unwrap_or_default(),
+            CsvColumn::EndTime => action.end_time().map(|time| time.to_string()).unwrap_or_default(),
+            CsvColumn::ValueSize => action.value_size().map(|size| size.to_string()).unwrap_or_default(),
+            CsvColumn::OperationHash => b58::<OperationHash>(action.operation_hash()).unwrap_or_default(),
+            CsvColumn::Record => msg.record.to_string(),
+            CsvColumn::Perform => msg.perform.to_string(),
+        }
+    }
+}
+
+impl FromStr for CsvColumn {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        CsvColumn::ALL
+            .iter()
+            .find(|column| column.name() == s)
+            .copied()
+            .ok_or_else(|| anyhow!("Unknown column: {}", s))
+    }
+}
+
+/// Streams one CSV row with `columns` per action, returns the number of rows written
+pub fn export_csv<W: Write>(reader: ActionsFileReader, out: W, columns: &[CsvColumn]) -> Result<u64> {
+    let mut out = csv::Writer::from_writer(BufWriter::new(out));
+    out.write_record(columns.iter().map(CsvColumn::name))?;
+    let mut rows = 0;
+    for item in reader {
+        let (block, actions) = item?;
+        let block_hash = block.block_hash.to_string();
+        for msg in &actions {
+            out.write_record(columns.iter().map(|column| column.value(block.block_level, &block_hash, msg)))?;
+            rows += 1;
+        }
+    }
+    out.flush()?;
+    Ok(rows)
+}
+
+/// Formats a raw hash in b58check, `None` if it is missing or not a valid hash
+fn b58<H: for<'a> TryFrom<&'a [u8]> + Display>(hash: Option<&Vec<u8>>) -> Option<String> {
+    let hash = H::try_from(hash?.as_slice()).ok()?;
+    Some(hash.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use io::channel::{ActionKind, ContextAction};
+    use io::{ActionFilter, ActionsFileWriter, Block};
+
+    fn set(key: &str, value: &[u8]) -> ContextActionMessage {
+        ContextActionMessage {
+            action: ContextAction::Set {
+                context_hash: None,
+                block_hash: None,
+                operation_hash: Some(vec![5; 32]),
+                tree_hash: vec![],
+                new_tree_hash: vec![],
+                start_time: 1.0,
+                end_time: 1.5,
+                key: key.split('/').map(str::to_string).collect(),
+                value: value.to_vec(),
+                value_as_json: None,
+            },
+            record: true,
+            perform: true,
+        }
+    }
+
+    #[test]
+    fn test_export_sqlite_resumes() {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("actions.bin");
+        let db = dir.path().join("actions.db");
+        let mut writer = ActionsFileWriter::new(&bin).unwrap();
+        writer.update(Block::new(1, vec![1; 32], vec![0; 32]), vec![set("data/contracts/index/a", b"1")]).unwrap();
+        writer.update(Block::new(2, vec![2; 32], vec![1; 32]), vec![set("data/contracts/index/a", b"22"), set("data/big_maps/x", b"")]).unwrap();
+
+        assert_eq!(export_sqlite(&bin, ActionFilter::new(), &db).unwrap(), 2);
+        writer.update(Block::new(3, vec![3; 32], vec![2; 32]), vec![set("data/contracts/index/b", b"333")]).unwrap();
+        assert_eq!(export_sqlite(&bin, ActionFilter::new().from_level(1), &db).unwrap(), 1);
+
+        let conn = rusqlite::Connection::open(&db).unwrap();
+        let levels: Vec<u32> = conn
+            .prepare("SELECT DISTINCT a.level FROM actions a JOIN keys k ON k.id = a.key_id \
+                      WHERE a.kind = 'set' AND k.path GLOB 'data/contracts/index/*' ORDER BY a.level").unwrap()
+            .query_map([], |row| row.get(0)).unwrap()
+            .collect::<rusqlite::Result<_>>().unwrap();
+        assert_eq!(levels, vec![1, 2, 3]);
+        let keys: u32 = conn.query_row("SELECT COUNT(*) FROM keys", [], |row| row.get(0)).unwrap();
+        assert_eq!(keys, 3);
+        let size: u32 = conn.query_row("SELECT value_size FROM actions WHERE level = 3", [], |row| row.get(0)).unwrap();
+        assert_eq!(size, 3);
+    }
+
+    #[test]
+    fn test_export_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("actions.bin");
+        let mut writer = ActionsFileWriter::new(&bin).unwrap();
+        for level in 1..=3 {
+            let checkout = ContextActionMessage {
+                action: ContextAction::Checkout { context_hash: vec![1; 32], start_time: 1.0, end_time: 1.5 },
+                record: true,
+                perform: true,
+            };
+            writer.update(Block::new(level, vec![level as u8; 32], vec![level as u8 - 1; 32]), vec![checkout, set("data/a", b"xy")]).unwrap();
+        }
+
+        let columns: Vec<CsvColumn> = vec!["level".parse().unwrap(), "key".parse().unwrap(), "value_size".parse().unwrap()];
+        let reader = ActionsFileReader::new(&bin).unwrap().with_filter(ActionFilter::new().levels(2..=3).kind(ActionKind::Set));
+        let mut out = vec![];
+        assert_eq!(export_csv(reader, &mut out, &columns).unwrap(), 2);
+        assert_eq!(String::from_utf8(out).unwrap(), "level,key,value_size\n2,data/a,2\n3,data/a,2\n");
+        assert!("nope".parse::<CsvColumn>().is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use io::channel::{BlockJson, ContextActionJson, ContextActionMessage};
+use io::hash::BlockHash;
+use io::jsonl::ActionLine;
+use io::{ActionsFileReader, ActionsFileWriter, Block};
+
+/// Writes saved node RPC responses found in `inputs` to the actions file at `output`
+///
+/// Every `*.json` file named after a block hash is read as the response of
+/// `/dev/chains/main/actions/blocks/{hash}`, any other `*.json` file as a response of
+/// `/dev/chains/main/blocks`. Blocks are written in level order and each needs its actions file.
+/// Returns the number of blocks written.
+pub fn import_json<P: AsRef<Path>>(inputs: &[P], output: P) -> Result<u32> {
+    let mut blocks = BTreeMap::new();
+    let mut actions = HashMap::new();
+    for input in inputs {
+        for entry in fs::read_dir(input)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+            match stem.parse::<BlockHash>() {
+                Ok(hash) => {
+                    actions.insert(hash, path);
+                }
+                Err(_) => {
+                    for block in read_json::<Vec<BlockJson>>(&path)? {
+                        blocks.insert(block.header.level, Block::from(block));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut writer = ActionsFileWriter::new(output)?;
+    let mut count = 0;
+    for block in blocks.into_values() {
+        let path = actions.get(&block.block_hash).ok_or_else(|| {
+            anyhow!("No actions response for block {} at level {}", block.block_hash, block.block_level)
+        })?;
+        let actions = read_json::<Vec<ContextActionJson>>(path)?
+            .into_iter()
+            .map(ContextActionMessage::from)
+            .collect();
+        writer.update(block, actions)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Writes the JSON Lines export read from `input` back to the actions file at `output`
+///
+/// Consecutive lines of the same block form one block. Returns the number of blocks written.
+pub fn import_jsonl<R: BufRead, P: AsRef<Path>>(input: R, output: P) -> Result<u32> {
+    let mut writer = ActionsFileWriter::new(output)?;
+    let mut current: Option<(Block, Vec<ContextActionMessage>)> = None;
+    let mut count = 0;
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line = serde_json::from_str::<ActionLine>(&line).with_context(|| format!("Invalid line {}", number + 1))?;
+        let message = line.message().with_context(|| format!("Invalid action on line {}", number + 1))?;
+        match &mut current {
+            Some((block, actions)) if block.block_hash == line.block_hash => {
+                actions.extend(message);
+            }
+            _ => {
+                if let Some((block, actions)) = current.take() {
+                    writer.update(block, actions)?;
+                    count += 1;
+                }
+                current = Some((line.block(), message.into_iter().collect()));
+            }
+        }
+    }
+    if let Some((block, actions)) = current {
+        writer.update(block, actions)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Rewrites the actions file at `input`, in any format version the reader supports, to a new
+/// file at `output` in the current format version
+///
+/// Returns the number of blocks written.
+pub fn migrate<P: AsRef<Path>>(input: P, output: P) -> Result<u32> {
+    let reader = ActionsFileReader::new(input)?;
+    let mut writer = ActionsFileWriter::new(output.as_ref())?;
+    if writer.header().block_count > 0 {
+        return Err(anyhow!("{} already has blocks", output.as_ref().display()));
+    }
+    let mut count = 0;
+    for item in reader {
+        let (block, actions) = item?;
+        writer.update(block, actions)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &PathBuf) -> Result<T> {
+    let file = File::open(path)?;
+    serde_json::from_reader(BufReader::new(file)).with_context(|| format!("Invalid RPC response {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use io::channel::{BlockHeaderJson, ContextAction};
+
+    fn hash(level: u32) -> BlockHash {
+        let mut hash = [0_u8; 32];
+        hash[0] = level as u8;
+        BlockHash::new(hash)
+    }
+
+    #[test]
+    fn test_import_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let dumps = dir.path().join("dumps");
+        fs::create_dir(&dumps).unwrap();
+        for level in 1..=3_u32 {
+            let blocks = vec![BlockJson { hash: hash(level), header: BlockHeaderJson { level, predecessor: hash(level - 1) } }];
+            fs::write(dumps.join(format!("blocks-{}.json", level)), serde_json::to_string(&blocks).unwrap()).unwrap();
+            let actions = vec![ContextActionJson {
+                action: ContextAction::Checkout { context_hash: vec![level as u8; 32], start_time: 0.0, end_time: 1.0 },
+            }];
+            fs::write(dumps.join(format!("{}.json", hash(level))), serde_json::to_string(&actions).unwrap()).unwrap();
+        }
+        let output = dir.path().join("actions.bin");
+
+        assert_eq!(import_json(&[&dumps], &output).unwrap(), 3);
+        let levels: Vec<_> = ActionsFileReader::new(&output).unwrap().map(|item| {
+            let (block, actions) = item.unwrap();
+            assert_eq!(actions.len(), 1);
+            block.block_level
+        }).collect();
+        assert_eq!(levels, vec![1, 2, 3]);
+
+        fs::remove_file(dumps.join(format!("{}.json", hash(2)))).unwrap();
+        assert!(import_json(&[&dumps], &dir.path().join("missing.bin")).is_err());
+    }
+
+    #[test]
+    fn test_jsonl_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("original.bin");
+        let mut writer = ActionsFileWriter::new(&original).unwrap();
+        for level in 1..=3_u32 {
+            let actions = (0..level - 1).map(|i| ContextActionMessage {
+                action: ContextAction::Checkout {
+                    context_hash: vec![i as u8; 32],
+                    start_time: 1612345678.123456 + f64::from(level * 7 + i) * 0.000_137,
+                    end_time: 1612345678.987654 + f64::from(level * 11 + i) * 0.000_291,
+                },
+                record: true,
+                perform: i % 2 == 0,
+            }).collect();
+            writer.update(Block { block_level: level, block_hash: hash(level), predecessor: hash(level - 1) }, actions).unwrap();
+        }
+
+        let mut jsonl = vec![];
+        let lines = crate::export::export_jsonl(ActionsFileReader::new(&original).unwrap(), &mut jsonl).unwrap();
+        assert_eq!(lines, 4);
+        let imported = dir.path().join("imported.bin");
+        assert_eq!(import_jsonl(&jsonl[..], &imported).unwrap(), 3);
+        assert_eq!(fs::read(&original).unwrap(), fs::read(&imported).unwrap());
+    }
+}
@@ -1,13 +1,21 @@
-use clap::{Arg, App};
-use io::ActionsFileReader;
+mod action_stats;
+#[cfg(feature = "columnar")]
+mod columnar;
+mod export;
+mod import;
+mod profile;
+mod storage;
+mod trace;
+
+use clap::{Arg, App, ArgMatches};
+use io::channel::FlagFilter;
+use io::{ActionFilter, ActionsFileReader};
 use std::time::Instant;
 use jemalloc_ctl::{stats, epoch};
-use io::channel::{ContextAction};
 
-use merkle::prelude::{MerkleStorageStats, MerklePerfStats, MerkleError, HashType};
+use merkle::prelude::{MerkleStorageStats, MerklePerfStats, MerkleError};
 
 use std::sync::{RwLock, Arc};
-use std::convert::TryInto;
 use flate2::read::DeflateDecoder;
 use flate2::write::DeflateEncoder;
 use flate2::Compression;
@@ -17,6 +25,10 @@ use std::fs::{File, OpenOptions, read};
 
 
 fn main() {
+    let mut export_formats = vec!["jsonl", "sqlite", "csv"];
+    if cfg!(feature = "columnar") {
+        export_formats.extend_from_slice(&["arrow", "parquet"]);
+    }
     let matches = clap::App::new("Tezedge Action Bin Tool")
         .author("mambisi.zempare@simplestaking.com")
         .subcommand(App::new("print")
@@ -37,6 +49,7 @@ fn main() {
                 .takes_value(true)
                 .conflicts_with("head")
             )
+            .args(&block_filter_args())
         )
         .subcommand(App::new("benchmark")
             .about("benchmarks read speed")
@@ -73,6 +86,195 @@ fn main() {
             .takes_value(true)
         )
         )
+        .subcommand(App::new("import-json")
+            .about("Imports saved node RPC responses into an actions file")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("DIR")
+                .help("directory of saved blocks and actions responses, actions files are named <block hash>.json")
+                .takes_value(true)
+                .multiple(true)
+                .required(true)
+            ).arg(Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .value_name("FILE NAME")
+            .help("output file")
+            .takes_value(true)
+            .required(true)
+        )
+        )
+        .subcommand(App::new("migrate")
+            .about("Rewrites an actions file of an older format version in the current one")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("FILE NAME")
+                .help("Action bin file in an older format version")
+                .takes_value(true)
+                .required(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("FILE NAME")
+                .help("new Action bin file")
+                .takes_value(true)
+                .required(true)
+            )
+        )
+        .subcommand(App::new("export")
+            .about("Exports actions to another format")
+            .args(&filter_args())
+            .arg(Arg::with_name("file")
+                .short("f")
+                .long("file")
+                .value_name("FILE NAME")
+                .help("Action bin file")
+                .takes_value(true)
+                .required(true)
+            )
+            .arg(Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("output format, arrow and parquet need the columnar feature")
+                .possible_values(&export_formats)
+                .default_value("jsonl")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("FILE NAME")
+                .help("output file, stdout if not set; sqlite exports resume into an existing database")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("columns")
+                .long("columns")
+                .value_name("COLUMNS")
+                .help("comma separated csv columns: level, block_hash, kind, key, duration, start_time, end_time, value_size, operation_hash, record, perform")
+                .takes_value(true)
+                .use_delimiter(true)
+            )
+        )
+        .subcommand(App::new("import")
+            .about("Imports actions exported with the export subcommand")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("FILE NAME")
+                .help("input file, stdin if not set")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("input format")
+                .possible_values(&["jsonl"])
+                .default_value("jsonl")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("FILE NAME")
+                .help("Action bin file")
+                .takes_value(true)
+                .required(true)
+            )
+        )
+        .subcommand(App::new("profile")
+            .about("Aggregates action durations by kind and key path into folded stacks, for inferno or flamegraph.pl")
+            .args(&filter_args())
+            .arg(Arg::with_name("file")
+                .short("f")
+                .long("file")
+                .value_name("FILE NAME")
+                .help("Action bin file")
+                .takes_value(true)
+                .required(true)
+            )
+            .arg(Arg::with_name("depth")
+                .short("d")
+                .long("depth")
+                .value_name("SEGMENTS")
+                .help("number of key path segments below the action kind")
+                .default_value("3")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("FILE NAME")
+                .help("output file, stdout if not set")
+                .takes_value(true)
+            )
+        )
+        .subcommand(App::new("replay")
+            .about("Replays actions to a recorder listening on a Unix socket")
+            .arg(Arg::with_name("file")
+                .short("f")
+                .long("file")
+                .value_name("FILE NAME")
+                .help("Action bin file")
+                .takes_value(true)
+                .required(true)
+            )
+            .arg(Arg::with_name("socket")
+                .short("s")
+                .long("socket")
+                .value_name("PATH")
+                .help("Unix socket the recorder listens on")
+                .takes_value(true)
+                .required(true)
+            )
+            .arg(Arg::with_name("pacing")
+                .long("pacing")
+                .value_name("PACING")
+                .help("unpaced sends as fast as the recorder takes actions, recorded keeps their original timing")
+                .possible_values(&["unpaced", "recorded"])
+                .default_value("unpaced")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("speed")
+                .long("speed")
+                .value_name("FACTOR")
+                .help("replays the original timing this many times as fast, overrides --pacing")
+                .takes_value(true)
+            )
+        )
+        .subcommand(App::new("stats")
+            .about("Counts blocks and actions by kind and by record and perform flag")
+            .args(&filter_args())
+            .arg(Arg::with_name("file")
+                .short("f")
+                .long("file")
+                .value_name("FILE NAME")
+                .help("Action bin file")
+                .takes_value(true)
+                .required(true)
+            )
+        )
+        .subcommand(App::new("trace")
+            .about("Writes actions as Chrome Trace Event JSON, for chrome://tracing or the Perfetto UI")
+            .args(&filter_args())
+            .arg(Arg::with_name("file")
+                .short("f")
+                .long("file")
+                .value_name("FILE NAME")
+                .help("Action bin file")
+                .takes_value(true)
+                .required(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("FILE NAME")
+                .help("output file, stdout if not set")
+                .takes_value(true)
+            )
+        )
         .subcommand(App::new("uncompress")
             .about("Compress bin file with flate2")
             .arg(Arg::with_name("input")
@@ -98,10 +300,11 @@ fn main() {
         }
 
         if let Some(file) = matches.value_of("block") {
-            let reader = ActionsFileReader::new(file).unwrap();
-            reader.for_each(|(block, _)| {
-                println!("[{:<10}] {}", block.block_level, HashType::BlockHash.hash_to_b58check(&block.block_hash))
-            })
+            let reader = ActionsFileReader::new(file).unwrap().with_filter(action_filter(matches));
+            for item in reader {
+                let (block, _) = item.expect("Error reading actions file");
+                println!("[{:<10}] {}", block.block_level, block.block_hash)
+            }
         }
 
         return;
@@ -151,6 +354,139 @@ fn main() {
         std::io::copy(&mut input_file, &mut writer).expect("Error coping file");
         return;
     }
+    if let Some(matches) = matches.subcommand_matches("import-json") {
+        let inputs: Vec<_> = matches.values_of("input").unwrap().collect();
+        let output = matches.value_of("output").unwrap();
+        match import::import_json(&inputs, output) {
+            Ok(count) => println!("Imported {} blocks", count),
+            Err(e) => panic!("{:?}", e),
+        }
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("migrate") {
+        let input = matches.value_of("input").unwrap();
+        let output = matches.value_of("output").unwrap();
+        match import::migrate(input, output) {
+            Ok(count) => println!("Migrated {} blocks", count),
+            Err(e) => panic!("{:?}", e),
+        }
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("export") {
+        let reader = ActionsFileReader::new(matches.value_of("file").unwrap()).unwrap().with_filter(action_filter(matches));
+        let output = matches.value_of("output");
+        let result = match matches.value_of("format").unwrap() {
+            "jsonl" => {
+                let out: Box<dyn std::io::Write> = match output {
+                    Some(path) => Box::new(File::create(path).expect("Error creating output file")),
+                    None => Box::new(std::io::stdout()),
+                };
+                export::export_jsonl(reader, out).map(|_| ())
+            }
+            "csv" => {
+                let out: Box<dyn std::io::Write> = match output {
+                    Some(path) => Box::new(File::create(path).expect("Error creating output file")),
+                    None => Box::new(std::io::stdout()),
+                };
+                let columns: Vec<export::CsvColumn> = match matches.values_of("columns") {
+                    Some(columns) => columns.map(|column| column.parse().expect("Invalid column")).collect(),
+                    None => export::CsvColumn::ALL.to_vec(),
+                };
+                export::export_csv(reader, out, &columns).map(|_| ())
+            }
+            "sqlite" => {
+                let output = output.expect("sqlite export needs an output file");
+                export::export_sqlite(matches.value_of("file").unwrap(), action_filter(matches), output)
+                    .map(|count| eprintln!("Exported {} blocks", count))
+            }
+            #[cfg(feature = "columnar")]
+            "arrow" => {
+                let output = output.expect("arrow export needs an output file");
+                let out = File::create(output).expect("Error creating output file");
+                columnar::export_arrow(reader, out).map(|rows| eprintln!("Exported {} actions", rows))
+            }
+            #[cfg(feature = "columnar")]
+            "parquet" => {
+                let output = output.expect("parquet export needs an output file");
+                let out = File::create(output).expect("Error creating output file");
+                columnar::export_parquet(reader, out).map(|rows| eprintln!("Exported {} actions", rows))
+            }
+            format => unreachable!("unsupported format {}", format),
+        };
+        if let Err(e) = result {
+            panic!("{:?}", e)
+        }
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("import") {
+        let output = matches.value_of("output").unwrap();
+        let input: Box<dyn std::io::BufRead> = match matches.value_of("input") {
+            Some(path) => Box::new(std::io::BufReader::new(File::open(path).expect("Error opening input file"))),
+            None => Box::new(std::io::BufReader::new(std::io::stdin())),
+        };
+        match import::import_jsonl(input, output) {
+            Ok(count) => eprintln!("Imported {} blocks", count),
+            Err(e) => panic!("{:?}", e),
+        }
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("profile") {
+        let reader = ActionsFileReader::new(matches.value_of("file").unwrap()).unwrap().with_filter(action_filter(matches));
+        let out: Box<dyn std::io::Write> = match matches.value_of("output") {
+            Some(path) => Box::new(File::create(path).expect("Error creating output file")),
+            None => Box::new(std::io::stdout()),
+        };
+        let depth = matches.value_of("depth").unwrap().parse().expect("Invalid depth");
+        match profile::profile(reader, depth).and_then(|profile| profile.write_folded(out)) {
+            Ok(count) => eprintln!("Wrote {} stacks", count),
+            Err(e) => panic!("{:?}", e),
+        }
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("replay") {
+        use io::channel::ActionChannel;
+        use io::ipc::{IpcConfig, IpcSender};
+        use io::replay::{replay, Pacing};
+
+        let reader = ActionsFileReader::new(matches.value_of("file").unwrap()).unwrap();
+        let pacing = match (matches.value_of("speed"), matches.value_of("pacing").unwrap()) {
+            (Some(speed), _) => Pacing::Scaled(speed.parse().expect("Invalid speed")),
+            (None, "recorded") => Pacing::Recorded,
+            (None, _) => Pacing::Unpaced,
+        };
+        let channel = ActionChannel::default();
+        let sender = IpcSender::new(matches.value_of("socket").unwrap(), IpcConfig::default());
+        let forwarding = {
+            let channel = channel.clone();
+            std::thread::spawn(move || sender.run(&channel))
+        };
+        let started = Instant::now();
+        match replay(reader, &channel, pacing) {
+            Ok(count) => eprintln!("Replayed {} actions in {:?}", count, started.elapsed()),
+            Err(e) => panic!("{:?}", e),
+        }
+        if let Err(e) = forwarding.join().unwrap() {
+            panic!("{:?}", e)
+        }
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("stats") {
+        let reader = ActionsFileReader::new(matches.value_of("file").unwrap()).unwrap().with_filter(action_filter(matches));
+        print!("{}", action_stats::Stats::collect(reader).expect("Error reading actions file"));
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("trace") {
+        let reader = ActionsFileReader::new(matches.value_of("file").unwrap()).unwrap().with_filter(action_filter(matches));
+        let out: Box<dyn std::io::Write> = match matches.value_of("output") {
+            Some(path) => Box::new(File::create(path).expect("Error creating output file")),
+            None => Box::new(std::io::stdout()),
+        };
+        match trace::export_trace(reader, out) {
+            Ok(count) => eprintln!("Traced {} events", count),
+            Err(e) => panic!("{:?}", e),
+        }
+        return;
+    }
     if let Some(matches) = matches.subcommand_matches("uncompress") {
         let input_path = matches.value_of("input").unwrap();
         let output_path = matches.value_of("output").unwrap();
@@ -165,63 +501,120 @@ fn main() {
 }
 
 
+/// Options selecting the blocks read from an actions file
+fn block_filter_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("from-level")
+            .long("from-level")
+            .value_name("LEVEL")
+            .help("first block level")
+            .takes_value(true),
+        Arg::with_name("to-level")
+            .long("to-level")
+            .value_name("LEVEL")
+            .help("last block level")
+            .takes_value(true),
+        Arg::with_name("block-hash")
+            .long("block-hash")
+            .value_name("HASHES")
+            .help("comma separated b58check block hashes")
+            .takes_value(true)
+            .use_delimiter(true),
+    ]
+}
+
+/// Options selecting the blocks and actions read from an actions file
+fn filter_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    let mut args = block_filter_args();
+    args.extend(vec![
+        Arg::with_name("kind")
+            .long("kind")
+            .value_name("KINDS")
+            .help("comma separated action kinds, e.g. set,get")
+            .takes_value(true)
+            .use_delimiter(true),
+        Arg::with_name("key")
+            .long("key")
+            .value_name("PREFIX")
+            .help("only actions with a key starting with these segments, e.g. data/contracts")
+            .takes_value(true)
+            .conflicts_with("key-glob"),
+        Arg::with_name("key-glob")
+            .long("key-glob")
+            .value_name("GLOB")
+            .help("only actions with a key matching the glob, e.g. data/contracts/index/*/balance")
+            .takes_value(true),
+        Arg::with_name("operation")
+            .long("operation")
+            .value_name("HASH")
+            .help("only actions of the b58check operation hash")
+            .takes_value(true),
+        Arg::with_name("recorded")
+            .long("recorded")
+            .help("only actions with the record flag"),
+        Arg::with_name("performed")
+            .long("performed")
+            .help("only actions with the perform flag"),
+    ]);
+    args
+}
+
+fn action_filter(matches: &ArgMatches) -> ActionFilter {
+    let mut filter = ActionFilter::new().flags(FlagFilter {
+        record: if matches.is_present("recorded") { Some(true) } else { None },
+        perform: if matches.is_present("performed") { Some(true) } else { None },
+    });
+    if let Some(level) = matches.value_of("from-level") {
+        filter = filter.from_level(level.parse().expect("Invalid from-level"));
+    }
+    if let Some(level) = matches.value_of("to-level") {
+        filter = filter.to_level(level.parse().expect("Invalid to-level"));
+    }
+    if let Some(hashes) = matches.values_of("block-hash") {
+        filter = filter.block_hashes(hashes.map(|hash| hash.parse().expect("Invalid block hash")));
+    }
+    if let Some(kinds) = matches.values_of("kind") {
+        filter = filter.kinds(kinds.map(|kind| kind.parse().expect("Invalid kind")));
+    }
+    if let Some(prefix) = matches.value_of("key") {
+        filter = filter.key_prefix(prefix);
+    }
+    if let Some(glob) = matches.value_of("key-glob") {
+        filter = filter.key_glob(glob);
+    }
+    if let Some(hash) = matches.value_of("operation") {
+        filter = filter.operation_hash(hash.parse().expect("Invalid operation hash"));
+    }
+    filter
+}
+
 fn validate_blocks_merkle_gc_enabled(reader: ActionsFileReader, cycle: u32) -> Result<MerkleStorageStats, MerkleError> {
+    use crate::storage::Applied;
     use merkle::prelude::*;
+    use std::collections::BTreeMap;
     let db = Arc::new(RwLock::new(DB::new()));
 
     let mut storage = MerkleStorage::new(db.clone());
-    reader.for_each(|(block, actions)| {
+    let mut unverified = BTreeMap::new();
+    for item in reader {
+        let (block, actions) = item.unwrap_or_else(|e| panic!("{}", e));
         let block_level = block.block_level;
         for msg in &actions {
             if msg.perform {
-                match &msg.action {
-                    ContextAction::Set { key, value, context_hash, .. } =>
-                        {
-                            storage.set(key, value);
-                        }
-                    ContextAction::Copy { to_key: key, from_key, context_hash, .. } =>
-                        {
-                            storage.copy(from_key, key);
-                        }
-                    ContextAction::Delete { key, context_hash, .. } =>
-                        {
-                            storage.delete(key);
-                        }
-
-                    ContextAction::RemoveRecursively { key, context_hash, .. } =>
-                        {
-                            storage.delete(key);
-                        }
-
-                    ContextAction::Commit {
-                        parent_context_hash, new_context_hash, block_hash: Some(block_hash),
-                        author, message, date, ..
-                    } => {
-                        let date = *date as u64;
-                        let hash = storage.commit(date, author.to_owned(), message.to_owned()).unwrap();
-                        let commit_hash = hash[..].to_vec();
-                        assert_eq!(
-                            &commit_hash,
-                            new_context_hash,
-                            "Invalid context_hash for block: {}, expected: {}, but was: {}",
-                            HashType::BlockHash.hash_to_b58check(block_hash),
-                            HashType::ContextHash.hash_to_b58check(new_context_hash),
-                            HashType::ContextHash.hash_to_b58check(&hash),
-                        );
-                    }
-
-                    ContextAction::Checkout { context_hash, .. } => {
-                        let context_hash_arr: EntryHash = context_hash.as_slice().try_into().unwrap();
-                        storage.checkout(&context_hash_arr);
-                    }
-                    _ => (),
-                };
+                match crate::storage::apply(&mut storage, block_level, &msg.action) {
+                    Ok(Applied::Unverified) => *unverified.entry(msg.action.kind()).or_insert(0_u64) += 1,
+                    Ok(_) => (),
+                    Err(e) => panic!("{}", e),
+                }
             }
         }
         if block_level != 0 && block_level % cycle == 0 {
             storage.gc();
         }
-    });
+    }
+    for (kind, count) in unverified {
+        eprintln!("WARNING: {} {} actions were not checked against the storage", count, kind);
+    }
     storage.get_merkle_stats()
 }
 
@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+use std::io::{BufWriter, Write};
+
+use anyhow::Result;
+use io::channel::ContextAction;
+use io::ActionsFileReader;
+
+/// Action times are in seconds, folded stack weights in microseconds
+const MICROS: f64 = 1_000_000.0;
+
+/// Total action durations of a chain segment, keyed by folded stack
+pub struct Profile {
+    /// Number of key segments kept after the kind
+    depth: usize,
+    stacks: BTreeMap<String, f64>,
+}
+
+impl Profile {
+    pub fn new(depth: usize) -> Self {
+        Profile {
+            depth,
+            stacks: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the duration of `action` to its stack, actions without timings are ignored
+    pub fn add(&mut self, action: &ContextAction) {
+        if action.start_time().is_none() {
+            return;
+        }
+        let mut stack = action.kind().to_string();
+        for segment in action.key().unwrap_or_default().iter().take(self.depth) {
+            stack.push(';');
+            stack.push_str(&frame(segment));
+        }
+        *self.stacks.entry(stack).or_default() += action.duration();
+    }
+
+    /// Writes one `frame;frame weight` line per stack, as read by inferno and flamegraph.pl
+    ///
+    /// Stacks that sum up to less than a microsecond are left out.
+    pub fn write_folded<W: Write>(&self, out: W) -> Result<usize> {
+        let mut out = BufWriter::new(out);
+        let mut lines = 0;
+        for (stack, duration) in &self.stacks {
+            let weight = (duration * MICROS).round() as u64;
+            if weight > 0 {
+                writeln!(out, "{} {}", stack, weight)?;
+                lines += 1;
+            }
+        }
+        out.flush()?;
+        Ok(lines)
+    }
+}
+
+/// Aggregates the actions of `reader` into a profile with `depth` key segments
+pub fn profile(reader: ActionsFileReader, depth: usize) -> Result<Profile> {
+    let mut profile = Profile::new(depth);
+    for item in reader {
+        let (_, actions) = item?;
+        actions.iter().for_each(|msg| profile.add(&msg.action));
+    }
+    Ok(profile)
+}
+
+/// Key segment as a frame name, `;` separates frames and whitespace the weight
+fn frame(segment: &str) -> String {
+    segment.replace(|c: char| c == ';' || c.is_whitespace(), "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(key: &str, start_time: f64, end_time: f64) -> ContextAction {
+        ContextAction::Get {
+            context_hash: None,
+            block_hash: None,
+            operation_hash: None,
+            tree_hash: vec![],
+            start_time,
+            end_time,
+            key: key.split('/').map(str::to_string).collect(),
+            value: vec![],
+            value_as_json: None,
+        }
+    }
+
+    #[test]
+    fn test_folded_stacks() {
+        let mut profile = Profile::new(3);
+        profile.add(&get("data/contracts/index/a", 1.0, 1.25));
+        profile.add(&get("data/contracts/index/b", 2.0, 2.5));
+        profile.add(&get("data/votes", 3.0, 3.000_000_1));
+        profile.add(&get("data/my key;x", 3.0, 3.5));
+        profile.add(&ContextAction::Checkout { context_hash: vec![], start_time: 4.0, end_time: 4.001 });
+        profile.add(&ContextAction::Shutdown);
+
+        let mut out = vec![];
+        assert_eq!(profile.write_folded(&mut out).unwrap(), 3);
+        assert_eq!(String::from_utf8(out).unwrap(), "checkout 1000\nget;data;contracts;index 750000\nget;data;my_key_x 500000\n");
+    }
+}
@@ -0,0 +1,121 @@
+use std::convert::{TryFrom, TryInto};
+use std::fmt::Display;
+
+use anyhow::{anyhow, Result};
+use merkle::prelude::{EntryHash, MerkleStorage};
+
+use io::channel::ContextAction;
+use io::hash::{BlockHash, ContextHash};
+
+/// What `apply` did with an action
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Applied {
+    /// A write, replayed on the storage
+    Replayed,
+    /// A read or commit whose recorded result matches the storage
+    Verified,
+    /// A read the storage cannot fully check, e.g. the tree hash returned by `Hash`
+    Unverified,
+}
+
+/// Applies `action` to the merkle `storage` and checks its recorded result.
+///
+/// Writes are replayed. `Commit` and `Find` are verified against the recorded outputs.
+/// `FindTree` and `List` are only checked as far as the found key and the listed children
+/// exist, and are reported as `Applied::Unverified` like the other reads, except a `FindTree`
+/// that found nothing.
+pub fn apply(storage: &mut MerkleStorage, block_level: u32, action: &ContextAction) -> Result<Applied> {
+    let applied = match action {
+        ContextAction::Set { key, value, .. } => {
+            storage.set(key, value).map_err(|e| anyhow!("{:?}", e))?;
+            Applied::Replayed
+        }
+        ContextAction::Copy { to_key, from_key, .. } => {
+            storage.copy(from_key, to_key).map_err(|e| anyhow!("{:?}", e))?;
+            Applied::Replayed
+        }
+        ContextAction::Delete { key, .. } | ContextAction::RemoveRecursively { key, .. } => {
+            storage.delete(key).map_err(|e| anyhow!("{:?}", e))?;
+            Applied::Replayed
+        }
+        ContextAction::AddTree { key, value, .. } => {
+            storage.delete(key).map_err(|e| anyhow!("{:?}", e))?;
+            for (path, leaf) in value {
+                let mut leaf_key = key.clone();
+                leaf_key.extend(path.iter().cloned());
+                storage.set(&leaf_key, leaf).map_err(|e| anyhow!("{:?}", e))?;
+            }
+            Applied::Replayed
+        }
+        ContextAction::Commit {
+            new_context_hash, block_hash: Some(block_hash),
+            author, message, date, ..
+        } => {
+            let hash = storage
+                .commit(*date as u64, author.to_owned(), message.to_owned())
+                .map_err(|e| anyhow!("{:?}", e))?;
+            if &hash[..] != new_context_hash.as_slice() {
+                return Err(anyhow!(
+                    "Invalid context_hash for block: {}, expected: {}, but was: {}",
+                    b58::<BlockHash>(block_hash),
+                    b58::<ContextHash>(new_context_hash),
+                    b58::<ContextHash>(&hash),
+                ));
+            }
+            Applied::Verified
+        }
+        ContextAction::Checkout { context_hash, .. } => {
+            let context_hash: EntryHash = context_hash
+                .as_slice()
+                .try_into()
+                .map_err(|_| anyhow!("Invalid checkout context_hash at block level: {}", block_level))?;
+            storage.checkout(&context_hash).map_err(|e| anyhow!("{:?}", e))?;
+            Applied::Replayed
+        }
+        ContextAction::Find { key, value, .. } => {
+            // `None` is a missing key, which is not the same as a present empty value
+            let found = match storage.mem(key).map_err(|e| anyhow!("{:?}", e))? {
+                true => Some(storage.get(key).map_err(|e| anyhow!("{:?}", e))?),
+                false => None,
+            };
+            if &found != value {
+                return Err(anyhow!("Invalid find result for key: {} at block level: {}", key.join("/"), block_level));
+            }
+            Applied::Verified
+        }
+        ContextAction::FindTree { key, value, .. } => {
+            // The key may name a value as well as a tree, whose hash the storage does not expose
+            let found = storage.mem(key).map_err(|e| anyhow!("{:?}", e))?
+                || storage.dirmem(key).map_err(|e| anyhow!("{:?}", e))?;
+            if found != value.is_some() {
+                return Err(anyhow!("Invalid find_tree result for key: {} at block level: {}", key.join("/"), block_level));
+            }
+            match value {
+                Some(_) => Applied::Unverified,
+                None => Applied::Verified,
+            }
+        }
+        ContextAction::List { key, value, .. } => {
+            for name in value {
+                let mut child = key.clone();
+                child.push(name.clone());
+                let exists = storage.mem(&child).map_err(|e| anyhow!("{:?}", e))?
+                    || storage.dirmem(&child).map_err(|e| anyhow!("{:?}", e))?;
+                if !exists {
+                    return Err(anyhow!("Invalid list result for key: {}, missing child: {} at block level: {}", key.join("/"), name, block_level));
+                }
+            }
+            Applied::Unverified
+        }
+        _ => Applied::Unverified,
+    };
+    Ok(applied)
+}
+
+fn b58<H>(bytes: &[u8]) -> String
+    where H: for<'a> TryFrom<&'a [u8]> + Display {
+    match H::try_from(bytes) {
+        Ok(hash) => hash.to_string(),
+        Err(_) => format!("{:?}", bytes),
+    }
+}
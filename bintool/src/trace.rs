@@ -0,0 +1,153 @@
+use std::convert::TryFrom;
+use std::io::{BufWriter, Write};
+
+use anyhow::Result;
+use io::channel::ContextActionMessage;
+use io::hash::OperationHash;
+use io::{ActionsFileReader, Block};
+use serde_json::{json, Value};
+
+/// Action times are in seconds, trace event times in microseconds
+const MICROS: f64 = 1_000_000.0;
+
+/// Writes the actions of `reader` as Chrome Trace Event JSON, returns the number of events
+///
+/// Every block becomes a slice spanning its actions, consecutive actions of the same operation
+/// are grouped into an operation slice inside it and every action is a slice of its own.
+/// All slices are on one track, so viewers nest them by time. The output loads in
+/// `chrome://tracing` and in the Perfetto UI.
+pub fn export_trace<W: Write>(reader: ActionsFileReader, out: W) -> Result<u64> {
+    let mut out = BufWriter::new(out);
+    out.write_all(b"{\"displayTimeUnit\":\"ms\",\"traceEvents\":[")?;
+    let mut count = 0;
+    for item in reader {
+        let (block, actions) = item?;
+        for event in block_events(&block, &actions) {
+            if count > 0 {
+                out.write_all(b",\n")?;
+            }
+            serde_json::to_writer(&mut out, &event)?;
+            count += 1;
+        }
+    }
+    out.write_all(b"]}\n")?;
+    out.flush()?;
+    Ok(count)
+}
+
+/// Events of one block, parents before their children
+fn block_events(block: &Block, actions: &[ContextActionMessage]) -> Vec<Value> {
+    let timed: Vec<&ContextActionMessage> = actions.iter()
+        .filter(|msg| msg.action.start_time().is_some())
+        .collect();
+    let (start, end) = match span(&timed) {
+        Some(span) => span,
+        None => return vec![],
+    };
+
+    let mut events = vec![slice(format!("block {}", block.block_level), "block", start, end, json!({
+        "level": block.block_level,
+        "block_hash": block.block_hash.to_string(),
+    }))];
+    let mut rest = &timed[..];
+    while let Some(first) = rest.first() {
+        let operation = first.action.operation_hash();
+        let len = rest.iter().take_while(|msg| msg.action.operation_hash() == operation).count();
+        let (group, tail) = rest.split_at(len);
+        if let Some(hash) = operation.and_then(|hash| OperationHash::try_from(hash.as_slice()).ok()) {
+            let (start, end) = span(group).unwrap();
+            events.push(slice(format!("operation {}", hash), "operation", start, end, json!({
+                "operation_hash": hash.to_string(),
+            })));
+        }
+        events.extend(group.iter().map(|msg| action_event(msg)));
+        rest = tail;
+    }
+    events
+}
+
+fn action_event(msg: &ContextActionMessage) -> Value {
+    let action = &msg.action;
+    let mut args = json!({ "record": msg.record, "perform": msg.perform });
+    if let Some(key) = action.key() {
+        args["key"] = Value::from(key.join("/"));
+    }
+    if let Some(size) = action.value_size() {
+        args["value_size"] = Value::from(size);
+    }
+    let start = action.start_time().unwrap_or_default();
+    let end = action.end_time().unwrap_or(start);
+    slice(action.kind().to_string(), "action", start, end, args)
+}
+
+/// Earliest start and latest end of timed actions
+fn span(actions: &[&ContextActionMessage]) -> Option<(f64, f64)> {
+    let start = actions.iter().filter_map(|msg| msg.action.start_time()).fold(None, |min: Option<f64>, time| {
+        Some(min.map_or(time, |min| min.min(time)))
+    })?;
+    let end = actions.iter().filter_map(|msg| msg.action.end_time()).fold(start, f64::max);
+    Some((start, end))
+}
+
+/// Complete event, `ph` `X`, on the single track of the trace
+fn slice(name: String, category: &str, start: f64, end: f64, args: Value) -> Value {
+    json!({
+        "name": name,
+        "cat": category,
+        "ph": "X",
+        "ts": start * MICROS,
+        "dur": (end - start).max(0.0) * MICROS,
+        "pid": 1,
+        "tid": 1,
+        "args": args,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use io::channel::ContextAction;
+    use io::{ActionFilter, ActionsFileWriter};
+
+    fn get(operation_hash: Option<Vec<u8>>, start_time: f64, end_time: f64) -> ContextActionMessage {
+        ContextActionMessage {
+            action: ContextAction::Get {
+                context_hash: None,
+                block_hash: None,
+                operation_hash,
+                tree_hash: vec![],
+                start_time,
+                end_time,
+                key: vec!["data".to_string(), "votes".to_string()],
+                value: vec![1, 2, 3],
+                value_as_json: None,
+            },
+            record: true,
+            perform: true,
+        }
+    }
+
+    #[test]
+    fn test_export_trace() {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("actions.bin");
+        let mut writer = ActionsFileWriter::new(&bin).unwrap();
+        writer.update(Block::new(1, vec![1; 32], vec![0; 32]), vec![get(None, 1.0, 2.0)]).unwrap();
+        let actions = vec![get(None, 3.0, 3.5), get(Some(vec![5; 32]), 4.0, 4.5), get(Some(vec![5; 32]), 4.5, 5.0)];
+        writer.update(Block::new(2, vec![2; 32], vec![1; 32]), actions).unwrap();
+
+        let reader = ActionsFileReader::new(&bin).unwrap().with_filter(ActionFilter::new().levels(2..=2));
+        let mut out = vec![];
+        assert_eq!(export_trace(reader, &mut out).unwrap(), 5);
+        let trace: Value = serde_json::from_slice(&out).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        let categories: Vec<_> = events.iter().map(|event| event["cat"].as_str().unwrap()).collect();
+        assert_eq!(categories, vec!["block", "action", "operation", "action", "action"]);
+        assert_eq!(events[0]["ts"], Value::from(3_000_000.0));
+        assert_eq!(events[0]["dur"], Value::from(2_000_000.0));
+        assert_eq!(events[2]["dur"], Value::from(1_000_000.0));
+        assert_eq!(events[3]["name"], Value::from("get"));
+        assert_eq!(events[3]["args"]["key"], Value::from("data/votes"));
+        assert_eq!(events[3]["args"]["record"], Value::from(true));
+    }
+}
@@ -0,0 +1,273 @@
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::{bounded, Receiver, RecvTimeoutError, SendTimeoutError, Sender};
+
+use crate::channel::{ActionChannel, ChannelMetrics, ContextAction, ContextActionMessage, OverflowPolicy, POLL_INTERVAL};
+
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Fans every message out to all subscribers
+///
+/// Each subscriber receives from an `ActionChannel` of its own, with its own capacity and
+/// overflow policy, so a subscriber that falls behind only loses its own messages. Messages
+/// reach a subscriber's channel through a forwarding thread of its own, behind a queue as long
+/// as the channel. A subscriber with `OverflowPolicy::Block` holds up neither the sender nor the
+/// others until that queue is full too, then `send` waits for it.
+pub struct Broadcast {
+    subscribers: RwLock<Vec<Arc<Subscriber>>>,
+    shutdown_timeout: Duration,
+}
+
+struct Subscriber {
+    channel: ActionChannel,
+    queue: Sender<ContextActionMessage>,
+    /// Disconnects once the forwarder returned
+    finished: Receiver<()>,
+}
+
+/// Whether the subscriber still holds its channel, besides the broadcast and the forwarder
+fn is_subscribed(channel: &ActionChannel) -> bool {
+    channel.handles() > 2
+}
+
+impl Subscriber {
+    fn new(channel: ActionChannel) -> Self {
+        let (queue, forwarded) = bounded::<ContextActionMessage>(channel.capacity());
+        let (running, finished) = bounded::<()>(0);
+        {
+            let channel = channel.clone();
+            thread::spawn(move || {
+                let _running = running;
+                loop {
+                    match forwarded.recv_timeout(POLL_INTERVAL) {
+                        Ok(ContextActionMessage { action: ContextAction::Shutdown, .. }) => {
+                            channel.shutdown();
+                            return;
+                        }
+                        Ok(msg) => {
+                            if let Err(SendTimeoutError::Disconnected(_)) = channel.send(msg) {
+                                return;
+                            }
+                        }
+                        Err(RecvTimeoutError::Timeout) if is_subscribed(&channel) => {}
+                        Err(_) => {
+                            channel.close();
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+        Subscriber { channel, queue, finished }
+    }
+
+    /// Whether the subscriber still holds its channel, closing it otherwise
+    ///
+    /// Closing fails a send the forwarder is blocked in, so the forwarder returns.
+    fn is_subscribed(&self) -> bool {
+        let subscribed = is_subscribed(&self.channel);
+        if !subscribed {
+            self.channel.close();
+        }
+        subscribed
+    }
+
+    /// Queues `msg` for the forwarder, waiting for room until `deadline`
+    ///
+    /// Gives up once the subscriber is gone.
+    fn push(&self, mut msg: ContextActionMessage, deadline: Option<Instant>) -> bool {
+        loop {
+            let poll = Instant::now() + POLL_INTERVAL;
+            let until = deadline.map_or(poll, |deadline| deadline.min(poll));
+            match self.queue.send_deadline(msg, until) {
+                Ok(()) => return true,
+                Err(SendTimeoutError::Timeout(rejected)) => {
+                    if !self.is_subscribed() || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        return false;
+                    }
+                    msg = rejected;
+                }
+                Err(SendTimeoutError::Disconnected(_)) => return false,
+            }
+        }
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.channel.close();
+    }
+}
+
+impl Default for Broadcast {
+    fn default() -> Self {
+        Broadcast { subscribers: RwLock::default(), shutdown_timeout: SHUTDOWN_TIMEOUT }
+    }
+}
+
+impl Broadcast {
+    pub fn new() -> Self {
+        Broadcast::default()
+    }
+
+    /// How long `shutdown` waits for the subscribers to drain their channels, 10 seconds by default
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Adds a subscriber, it receives every message sent from now on until the channel is dropped
+    pub fn subscribe(&self, capacity: usize, policy: OverflowPolicy) -> ActionChannel {
+        let channel = ActionChannel::with_policy(capacity, policy);
+        let mut subscribers = self.subscribers.write().unwrap();
+        subscribers.retain(|subscriber| subscriber.is_subscribed());
+        subscribers.push(Arc::new(Subscriber::new(channel.clone())));
+        channel
+    }
+
+    /// Number of subscribers whose channels are still held
+    pub fn subscribers(&self) -> usize {
+        self.live().len()
+    }
+
+    /// The subscribers still holding their channels, the forwarders of the others are stopped
+    fn live(&self) -> Vec<Arc<Subscriber>> {
+        self.subscribers.read().unwrap().iter().filter(|subscriber| subscriber.is_subscribed()).cloned().collect()
+    }
+
+    /// Queues a copy of `msg` for every subscriber
+    ///
+    /// Only waits while the queue of a subscriber with `OverflowPolicy::Block` is full.
+    pub fn send(&self, msg: &ContextActionMessage) {
+        for subscriber in self.live() {
+            subscriber.push(msg.clone(), None);
+        }
+    }
+
+    /// Metrics of the subscribers, in the order they subscribed
+    pub fn metrics(&self) -> Vec<ChannelMetrics> {
+        self.live().iter().map(|subscriber| subscriber.channel.metrics()).collect()
+    }
+
+    /// Shuts down every subscriber, waiting for each to drain its channel until the shutdown timeout
+    ///
+    /// Closes the channels of the subscribers that did not drain in time and returns whether all
+    /// did. The subscribers are removed, messages sent afterwards go to new subscribers only.
+    pub fn shutdown(&self) -> bool {
+        let deadline = Instant::now() + self.shutdown_timeout;
+        let subscribers: Vec<_> = std::mem::take(&mut *self.subscribers.write().unwrap())
+            .into_iter()
+            .filter(|subscriber| subscriber.is_subscribed())
+            .collect();
+        let mut drained = true;
+        for subscriber in &subscribers {
+            drained &= subscriber.push(ContextActionMessage::shutdown(), Some(deadline));
+        }
+        for subscriber in subscribers {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if let Err(RecvTimeoutError::Timeout) = subscriber.finished.recv_timeout(timeout) {
+                subscriber.channel.close();
+                drained = false;
+            }
+        }
+        drained
+    }
+
+    /// Forwards the messages of `source` to the subscribers until `Shutdown`, then shuts them down
+    ///
+    /// The `Shutdown` of `source` is acknowledged once the subscribers drained their channels or
+    /// the shutdown timeout passed, so `source.shutdown()` waits for them.
+    pub fn run(&self, source: &ActionChannel) {
+        while let Ok(msg) = source.receive_unacknowledged() {
+            if let ContextAction::Shutdown = msg.action {
+                self.shutdown();
+                source.received(msg);
+                return;
+            }
+            self.send(&msg);
+        }
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::msg;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_fan_out() {
+        let broadcast = Broadcast::new();
+        let recorder = broadcast.subscribe(16, OverflowPolicy::Block);
+        let stats = broadcast.subscribe(1, OverflowPolicy::DropNewest);
+        let gone = broadcast.subscribe(1, OverflowPolicy::Block);
+        drop(gone);
+        assert_eq!(broadcast.subscribers(), 2);
+
+        broadcast.send(&msg(1));
+        broadcast.send(&msg(2));
+        while broadcast.metrics()[1].dropped == 0 {
+            thread::yield_now();
+        }
+        assert_eq!(stats.try_receive(), Ok(msg(1)));
+
+        let source = ActionChannel::new(16);
+        source.send(msg(3)).unwrap();
+        source.send(ContextActionMessage::shutdown()).unwrap();
+        let consumers: Vec<_> = vec![recorder, stats].into_iter()
+            .map(|channel| thread::spawn(move || channel.iter().collect::<Vec<_>>()))
+            .collect();
+        broadcast.run(&source);
+        let received: Vec<_> = consumers.into_iter().map(|consumer| consumer.join().unwrap()).collect();
+        assert_eq!(received, vec![vec![msg(1), msg(2), msg(3)], vec![msg(3)]]);
+    }
+
+    #[test]
+    fn test_blocked_subscriber_holds_up_nobody() {
+        let broadcast = Arc::new(Broadcast::new());
+        let blocked = broadcast.subscribe(1, OverflowPolicy::Block);
+        let live = broadcast.subscribe(16, OverflowPolicy::Block);
+        (1..=3).for_each(|hash| broadcast.send(&msg(hash)));
+        let late = broadcast.subscribe(16, OverflowPolicy::Block);
+        assert_eq!((1..=3).map(|_| live.receive().unwrap()).collect::<Vec<_>>(), (1..=3).map(msg).collect::<Vec<_>>());
+
+        let source = ActionChannel::new(16);
+        let running = {
+            let (broadcast, source) = (broadcast.clone(), source.clone());
+            thread::spawn(move || broadcast.run(&source))
+        };
+        let consumers: Vec<_> = vec![blocked.clone(), live.clone(), late.clone()].into_iter()
+            .map(|channel| thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                channel.iter().collect::<Vec<_>>()
+            }))
+            .collect();
+        source.send(msg(4)).unwrap();
+        source.shutdown();
+        assert!(blocked.is_empty() && live.is_empty() && late.is_empty());
+        running.join().unwrap();
+        let received: Vec<_> = consumers.into_iter().map(|consumer| consumer.join().unwrap()).collect();
+        assert_eq!(received, vec![(1..=4).map(msg).collect(), vec![msg(4)], vec![msg(4)]]);
+        assert_eq!(broadcast.subscribers(), 0);
+    }
+
+    #[test]
+    fn test_stalled_subscribers() {
+        let broadcast = Broadcast::new().with_shutdown_timeout(Duration::from_millis(100));
+        let stalled = broadcast.subscribe(1, OverflowPolicy::Block);
+        let gone = broadcast.subscribe(1, OverflowPolicy::Block);
+        (1..=3).for_each(|hash| broadcast.send(&msg(hash)));
+        drop(gone);
+        assert_eq!(broadcast.subscribers(), 1);
+
+        let started = Instant::now();
+        assert!(!broadcast.shutdown());
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(stalled.is_closed());
+        assert_eq!(stalled.try_receive(), Ok(msg(1)));
+    }
+}
@@ -1,45 +1,517 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
-use crossbeam::channel::{bounded, Receiver, RecvError, SendError, Sender};
+use crossbeam::channel::{
+    bounded, Receiver, RecvError, RecvTimeoutError, SendError, SendTimeoutError, Sender, TryRecvError, TrySendError,
+};
 use serde::{Deserialize, Serialize};
 
 use lazy_static::lazy_static;
 use std::cmp::Ordering::Equal;
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::hash::BlockHash;
+use crate::Block;
 
-static CHANNEL_ENABLED: AtomicBool = AtomicBool::new(false);
 const CHANNEL_BUFFER_LEN: usize = 1_048_576;
 
+/// How often a blocked send or receive checks whether the other side is gone
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 lazy_static! {
     /// This channel is shared by both OCaml and Rust
-    static ref CHANNEL: (Sender<ContextActionMessage>, Receiver<ContextActionMessage>) = bounded(CHANNEL_BUFFER_LEN);
+    ///
+    /// It is disabled by default, to prevent unit tests from overflowing it.
+    static ref CHANNEL: ActionChannel = {
+        let channel = ActionChannel::new(CHANNEL_BUFFER_LEN);
+        channel.disable();
+        channel
+    };
+}
+
+/// What `ActionChannel::send` does when the channel is full
+///
+/// Messages a policy rejects, timed out ones included, are counted in `ChannelMetrics::dropped`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Waits until the consumer makes room
+    #[default]
+    Block,
+    /// Drops the message being sent
+    DropNewest,
+    /// Drops the oldest queued message to make room, or the message being sent while a `Shutdown` is queued
+    DropOldest,
+    /// Waits up to the duration, then drops the message and fails with `SendTimeoutError::Timeout`
+    Timeout(Duration),
+}
+
+/// Counters of an `ActionChannel` since it was created
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChannelMetrics {
+    /// Messages queued
+    pub sent: u64,
+    /// Messages lost to the overflow policy
+    pub dropped: u64,
+    /// Messages discarded while the channel was disabled
+    pub discarded: u64,
+    /// Highest number of queued messages
+    pub high_water: usize,
+}
+
+/// `Shutdown` messages of a channel
+#[derive(Default)]
+struct Shutdowns {
+    /// Shutdowns queued or being queued
+    sent: u64,
+    /// Shutdowns received by the consumer, or never queued
+    received: u64,
+}
+
+/// State shared by the clones of an `ActionChannel`
+struct Shared {
+    enabled: AtomicBool,
+    policy: RwLock<OverflowPolicy>,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+    discarded: AtomicU64,
+    high_water: AtomicUsize,
+    /// Set by `close`, cleared when a consumer starts iterating
+    closed: AtomicBool,
+    shutdowns: Mutex<Shutdowns>,
+    drained: Condvar,
+}
+
+/// Bounded channel of `ContextActionMessage`s
+///
+/// Clones are handles to the same channel. While the channel is disabled, sent messages
+/// are discarded. A full channel is handled according to its `OverflowPolicy`.
+///
+/// Every handle can send and receive. Receiving fails as disconnected once the channel is empty
+/// and no other handle is left to send, sending once the consumer closed the channel.
+#[derive(Clone)]
+pub struct ActionChannel {
+    sender: Sender<ContextActionMessage>,
+    receiver: Receiver<ContextActionMessage>,
+    shared: Arc<Shared>,
+}
+
+impl ActionChannel {
+    /// Creates an enabled channel holding up to `capacity` messages, blocking when full
+    pub fn new(capacity: usize) -> Self {
+        Self::with_policy(capacity, OverflowPolicy::default())
+    }
+
+    pub fn with_policy(capacity: usize, policy: OverflowPolicy) -> Self {
+        let (sender, receiver) = bounded(capacity);
+        ActionChannel {
+            sender,
+            receiver,
+            shared: Arc::new(Shared {
+                enabled: AtomicBool::new(true),
+                policy: RwLock::new(policy),
+                sent: AtomicU64::new(0),
+                dropped: AtomicU64::new(0),
+                discarded: AtomicU64::new(0),
+                high_water: AtomicUsize::new(0),
+                closed: AtomicBool::new(false),
+                shutdowns: Mutex::new(Shutdowns::default()),
+                drained: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Sends a message, a full channel is handled according to the overflow policy.
+    ///
+    /// A `Shutdown` is never dropped, it waits for room like in `shutdown`. Fails as
+    /// `Disconnected` once the channel is closed, also while waiting for room.
+    // The rejected message is handed back, like crossbeam does
+    #[allow(clippy::result_large_err)]
+    pub fn send(&self, action: ContextActionMessage) -> Result<(), SendTimeoutError<ContextActionMessage>> {
+        if !self.is_enabled() {
+            self.shared.discarded.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        if let ContextAction::Shutdown = action.action {
+            return self.send_shutdown(action, None).map(|_| ());
+        }
+        if self.is_closed() {
+            return Err(SendTimeoutError::Disconnected(action));
+        }
+        match self.policy() {
+            OverflowPolicy::Block => self.send_until(action, None)?,
+            OverflowPolicy::DropNewest => match self.sender.try_send(action) {
+                Ok(()) => (),
+                Err(TrySendError::Full(_)) => {
+                    self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+                Err(TrySendError::Disconnected(action)) => return Err(SendTimeoutError::Disconnected(action)),
+            },
+            OverflowPolicy::DropOldest => {
+                let mut action = action;
+                loop {
+                    match self.sender.try_send(action) {
+                        Ok(()) => break,
+                        Err(TrySendError::Full(rejected)) => {
+                            // Holding the lock, no `Shutdown` can be queued until the oldest message is dropped
+                            let shutdowns = self.shared.shutdowns.lock().unwrap();
+                            if shutdowns.sent > shutdowns.received {
+                                // The oldest message may be a `Shutdown`, which must reach the consumer
+                                self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                                return Ok(());
+                            }
+                            // The consumer may have made room in the meantime
+                            if self.receiver.try_recv().is_ok() {
+                                self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                            }
+                            action = rejected;
+                        }
+                        Err(TrySendError::Disconnected(action)) => return Err(SendTimeoutError::Disconnected(action)),
+                    }
+                }
+            }
+            OverflowPolicy::Timeout(timeout) => {
+                if let Err(e) = self.send_until(action, Some(Instant::now() + timeout)) {
+                    if let SendTimeoutError::Timeout(_) = e {
+                        self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        self.shared.sent.fetch_add(1, Ordering::Relaxed);
+        self.shared.high_water.fetch_max(self.sender.len(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Sends `msg` once there is room, waiting until `deadline`
+    ///
+    /// Fails as `Disconnected` once the channel is closed, which is checked while waiting.
+    #[allow(clippy::result_large_err)]
+    fn send_until(&self, msg: ContextActionMessage, deadline: Option<Instant>) -> Result<(), SendTimeoutError<ContextActionMessage>> {
+        let mut msg = msg;
+        loop {
+            if self.is_closed() {
+                return Err(SendTimeoutError::Disconnected(msg));
+            }
+            let poll = Instant::now() + POLL_INTERVAL;
+            match self.sender.send_deadline(msg, deadline.map_or(poll, |deadline| deadline.min(poll))) {
+                Err(SendTimeoutError::Timeout(rejected)) if deadline.is_none_or(|deadline| Instant::now() < deadline) => {
+                    msg = rejected;
+                }
+                sent => return sent,
+            }
+        }
+    }
+
+    /// Receives a message, blocks while the channel is empty.
+    pub fn receive(&self) -> Result<ContextActionMessage, RecvError> {
+        self.receive_unacknowledged().map(|msg| self.received(msg))
+    }
+
+    /// Receives a message if one is waiting.
+    pub fn try_receive(&self) -> Result<ContextActionMessage, TryRecvError> {
+        match self.receiver.try_recv() {
+            Err(TryRecvError::Empty) if self.is_abandoned() => Err(TryRecvError::Disconnected),
+            received => received.map(|msg| self.received(msg)),
+        }
+    }
+
+    /// Receives a message, blocks up to `timeout` while the channel is empty.
+    pub fn receive_timeout(&self, timeout: Duration) -> Result<ContextActionMessage, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let poll = (Instant::now() + POLL_INTERVAL).min(deadline);
+            match self.receiver.recv_deadline(poll) {
+                Err(RecvTimeoutError::Timeout) if self.is_abandoned() => {
+                    return self.try_receive().map_err(|_| RecvTimeoutError::Disconnected);
+                }
+                Err(RecvTimeoutError::Timeout) if Instant::now() < deadline => (),
+                received => return received.map(|msg| self.received(msg)),
+            }
+        }
+    }
+
+    /// Receives up to `max` messages, blocks only while the channel is empty.
+    ///
+    /// The batch ends after a `Shutdown`.
+    pub fn receive_many(&self, max: usize) -> Result<Vec<ContextActionMessage>, RecvError> {
+        let mut batch = Vec::with_capacity(max.min(self.len() + 1));
+        if max == 0 {
+            return Ok(batch);
+        }
+        batch.push(self.receive()?);
+        while batch.len() < max && !matches!(batch.last(), Some(ContextActionMessage { action: ContextAction::Shutdown, .. })) {
+            match self.try_receive() {
+                Ok(msg) => batch.push(msg),
+                Err(_) => break,
+            }
+        }
+        Ok(batch)
+    }
+
+    /// Iterates over received messages until a `Shutdown`, which is not yielded.
+    ///
+    /// The iterator is a consumer in the sense of `close`: dropping it before the `Shutdown`
+    /// closes the channel, so `shutdown` does not wait for a consumer that returned early.
+    pub fn iter(&self) -> Messages<'_> {
+        self.shared.closed.store(false, Ordering::Release);
+        Messages {
+            channel: self,
+            done: false,
+        }
+    }
+
+    /// Sends `Shutdown` and waits until the consumer has received it,
+    /// and with it every message sent before.
+    ///
+    /// The message is sent even if the overflow policy would drop it. Returns at once if the
+    /// channel is disabled, and as soon as it is closed, as the consumer will not receive it then.
+    pub fn shutdown(&self) {
+        self.shutdown_until(None);
+    }
+
+    /// Like `shutdown`, but gives up waiting after `timeout`. Returns whether the consumer drained the channel.
+    pub fn shutdown_timeout(&self, timeout: Duration) -> bool {
+        self.shutdown_until(Some(Instant::now() + timeout))
+    }
+
+    fn shutdown_until(&self, deadline: Option<Instant>) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+        let ticket = match self.send_shutdown(ContextActionMessage::shutdown(), deadline) {
+            Ok(ticket) => ticket,
+            Err(_) => return false,
+        };
+
+        let mut shutdowns = self.shared.shutdowns.lock().unwrap();
+        while shutdowns.received < ticket {
+            if self.is_closed() {
+                return false;
+            }
+            shutdowns = match deadline {
+                None => self.shared.drained.wait(shutdowns).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    self.shared.drained.wait_timeout(shutdowns, deadline - now).unwrap().0
+                }
+            };
+        }
+        true
+    }
+
+    /// Queues a `Shutdown` whatever the overflow policy, returns the number the consumer has to receive to get to it
+    ///
+    /// Waits for room until `deadline`, and fails as `Disconnected` once the channel is closed.
+    #[allow(clippy::result_large_err)]
+    fn send_shutdown(&self, msg: ContextActionMessage, deadline: Option<Instant>) -> Result<u64, SendTimeoutError<ContextActionMessage>> {
+        let ticket = {
+            let mut shutdowns = self.shared.shutdowns.lock().unwrap();
+            shutdowns.sent += 1;
+            shutdowns.sent
+        };
+        if let Err(e) = self.send_until(msg, deadline) {
+            // Never reaches the consumer, so nothing has to wait for it
+            let mut shutdowns = self.shared.shutdowns.lock().unwrap();
+            shutdowns.received += 1;
+            self.shared.drained.notify_all();
+            return Err(e);
+        }
+        self.shared.sent.fetch_add(1, Ordering::Relaxed);
+        self.shared.high_water.fetch_max(self.sender.len(), Ordering::Relaxed);
+        Ok(ticket)
+    }
+
+    /// Receives a message like `receive`, but leaves acknowledging a `Shutdown` to `received`
+    pub(crate) fn receive_unacknowledged(&self) -> Result<ContextActionMessage, RecvError> {
+        loop {
+            match self.receiver.recv_timeout(POLL_INTERVAL) {
+                Err(RecvTimeoutError::Timeout) if !self.is_abandoned() => (),
+                // A message may have been sent right before the last other handle went away
+                Err(_) => return self.receiver.try_recv().map_err(|_| RecvError),
+                Ok(msg) => return Ok(msg),
+            }
+        }
+    }
+
+    /// Whether no other handle is left to send into the channel
+    fn is_abandoned(&self) -> bool {
+        self.handles() == 1
+    }
+
+    /// Acknowledges a received `Shutdown` to the waiting `shutdown` calls
+    pub(crate) fn received(&self, msg: ContextActionMessage) -> ContextActionMessage {
+        if let ContextAction::Shutdown = msg.action {
+            let mut shutdowns = self.shared.shutdowns.lock().unwrap();
+            shutdowns.received += 1;
+            self.shared.drained.notify_all();
+        }
+        msg
+    }
+
+    /// Tells producers that the consumer stopped receiving
+    ///
+    /// Waiting and later `shutdown` calls return instead of waiting for a `Shutdown` nobody
+    /// receives, and sends fail as `Disconnected`. Consumers that do not use `iter` call this
+    /// when they return before a `Shutdown`.
+    pub fn close(&self) {
+        self.shared.closed.store(true, Ordering::Release);
+        // Taking the lock, a `shutdown` that has not seen the flag is waiting for this notification
+        let _shutdowns = self.shared.shutdowns.lock().unwrap();
+        self.shared.drained.notify_all();
+    }
+
+    /// Whether the consumer stopped receiving, see `close`
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Acquire)
+    }
+
+    pub fn enable(&self) {
+        self.shared.enabled.store(true, Ordering::Release)
+    }
+
+    pub fn disable(&self) {
+        self.shared.enabled.store(false, Ordering::Release)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.shared.enabled.load(Ordering::Acquire)
+    }
+
+    pub fn policy(&self) -> OverflowPolicy {
+        *self.shared.policy.read().unwrap()
+    }
+
+    pub fn set_policy(&self, policy: OverflowPolicy) {
+        *self.shared.policy.write().unwrap() = policy;
+    }
+
+    pub fn metrics(&self) -> ChannelMetrics {
+        ChannelMetrics {
+            sent: self.shared.sent.load(Ordering::Relaxed),
+            dropped: self.shared.dropped.load(Ordering::Relaxed),
+            discarded: self.shared.discarded.load(Ordering::Relaxed),
+            high_water: self.shared.high_water.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Number of messages waiting in the channel
+    pub fn len(&self) -> usize {
+        self.receiver.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.receiver.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.receiver.capacity().unwrap_or(usize::MAX)
+    }
+
+    /// Number of handles to this channel
+    pub(crate) fn handles(&self) -> usize {
+        Arc::strong_count(&self.shared)
+    }
+}
+
+/// Iterator over the messages of an `ActionChannel`, see `ActionChannel::iter`
+pub struct Messages<'a> {
+    channel: &'a ActionChannel,
+    done: bool,
+}
+
+impl Iterator for Messages<'_> {
+    type Item = ContextActionMessage;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.channel.receive() {
+            Ok(ContextActionMessage { action: ContextAction::Shutdown, .. }) | Err(_) => {
+                self.done = true;
+                None
+            }
+            Ok(msg) => Some(msg),
+        }
+    }
+}
+
+impl Drop for Messages<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.channel.close();
+        }
+    }
+}
+
+impl Default for ActionChannel {
+    fn default() -> Self {
+        ActionChannel::new(CHANNEL_BUFFER_LEN)
+    }
+}
+
+/// The shared channel used by `context_send`, `context_receive` and `enable_context_channel`
+pub fn default_channel() -> &'static ActionChannel {
+    &CHANNEL
 }
 
 /// Send message into the shared channel.
+///
+/// Messages dropped by the overflow policy, timed out ones included, are not errors.
+/// `default_channel().send` reports timeouts.
+#[allow(clippy::result_large_err)]
 pub fn context_send(action: ContextActionMessage) -> Result<(), SendError<ContextActionMessage>> {
-    if CHANNEL_ENABLED.load(Ordering::Acquire) {
-        CHANNEL.0.send(action)
-    } else {
-        Ok(())
+    match CHANNEL.send(action) {
+        Err(SendTimeoutError::Disconnected(action)) => Err(SendError(action)),
+        Ok(()) | Err(SendTimeoutError::Timeout(_)) => Ok(()),
     }
 }
 
 /// Receive message from the shared channel.
 pub fn context_receive() -> Result<ContextActionMessage, RecvError> {
-    CHANNEL.1.recv()
+    CHANNEL.receive()
 }
 
 /// By default channel is disabled.
 ///
 /// This is needed to prevent unit tests from overflowing the shared channel.
 pub fn enable_context_channel() {
-    CHANNEL_ENABLED.store(true, Ordering::Release)
+    CHANNEL.enable()
+}
+
+/// Sets how the shared channel handles overflow, it blocks by default.
+pub fn set_context_overflow_policy(policy: OverflowPolicy) {
+    CHANNEL.set_policy(policy)
+}
+
+/// Counters of the shared channel.
+pub fn context_channel_metrics() -> ChannelMetrics {
+    CHANNEL.metrics()
 }
 
 type Hash = Vec<u8>;
 
+/// Highest variant tag this version of the crate can decode.
+///
+/// The tag of a persisted action is its bincode variant index, so variants that are
+/// written to files must never be reordered or removed. New variants are added right
+/// after the last persisted one (before `Unknown`) and this constant is bumped.
+pub const LAST_KNOWN_TAG: u32 = 14;
+
+/// Length of the little-endian variant tag that starts every encoded action.
+const TAG_LEN: usize = 4;
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ContextAction {
     Set {
@@ -142,91 +614,828 @@ pub enum ContextAction {
         end_time: f64,
         key: Vec<String>,
     },
+    Find {
+        context_hash: Option<Hash>,
+        block_hash: Option<Hash>,
+        operation_hash: Option<Hash>,
+        tree_hash: Hash,
+        start_time: f64,
+        end_time: f64,
+        key: Vec<String>,
+        value: Option<Vec<u8>>,
+    },
+    /// `value` is the hash of the tree found under `key`.
+    FindTree {
+        context_hash: Option<Hash>,
+        block_hash: Option<Hash>,
+        operation_hash: Option<Hash>,
+        tree_hash: Hash,
+        start_time: f64,
+        end_time: f64,
+        key: Vec<String>,
+        value: Option<Hash>,
+    },
+    /// `value` holds the leaves of the added tree, keyed relative to `key`.
+    AddTree {
+        context_hash: Option<Hash>,
+        block_hash: Option<Hash>,
+        operation_hash: Option<Hash>,
+        tree_hash: Hash,
+        new_tree_hash: Hash,
+        start_time: f64,
+        end_time: f64,
+        key: Vec<String>,
+        value: Vec<(Vec<String>, Vec<u8>)>,
+    },
+    /// `value` holds the names of the listed children, in the order they were returned.
+    List {
+        context_hash: Option<Hash>,
+        block_hash: Option<Hash>,
+        operation_hash: Option<Hash>,
+        tree_hash: Hash,
+        start_time: f64,
+        end_time: f64,
+        key: Vec<String>,
+        offset: Option<u32>,
+        length: Option<u32>,
+        value: Vec<String>,
+    },
+    /// `value` is the hash of the tree under `key`.
+    Hash {
+        context_hash: Option<Hash>,
+        block_hash: Option<Hash>,
+        operation_hash: Option<Hash>,
+        tree_hash: Hash,
+        start_time: f64,
+        end_time: f64,
+        key: Vec<String>,
+        value: Hash,
+    },
+    /// Action written by a newer version of this crate.
+    ///
+    /// `tag` is the variant tag found in the record and `raw` the full encoded action,
+    /// which is written back unchanged when the action is re-encoded.
+    Unknown {
+        tag: u32,
+        raw: Vec<u8>,
+    },
     /// This is a control event used to shutdown IPC channel
     Shutdown,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+impl ContextAction {
+    /// Returns the versioned variant tag of the action.
+    ///
+    /// `Shutdown` is never persisted and reports `u32::MAX`.
+    pub fn tag(&self) -> u32 {
+        match self {
+            ContextAction::Set { .. } => 0,
+            ContextAction::Delete { .. } => 1,
+            ContextAction::RemoveRecursively { .. } => 2,
+            ContextAction::Copy { .. } => 3,
+            ContextAction::Checkout { .. } => 4,
+            ContextAction::Commit { .. } => 5,
+            ContextAction::Mem { .. } => 6,
+            ContextAction::DirMem { .. } => 7,
+            ContextAction::Get { .. } => 8,
+            ContextAction::Fold { .. } => 9,
+            ContextAction::Find { .. } => 10,
+            ContextAction::FindTree { .. } => 11,
+            ContextAction::AddTree { .. } => 12,
+            ContextAction::List { .. } => 13,
+            ContextAction::Hash { .. } => 14,
+            ContextAction::Unknown { tag, .. } => *tag,
+            ContextAction::Shutdown => u32::MAX,
+        }
+    }
+
+    /// Encodes the action as its tag followed by the variant fields.
+    ///
+    /// `Unknown` actions are returned as the raw bytes they were decoded from.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        match self {
+            ContextAction::Unknown { raw, .. } => Ok(raw.clone()),
+            _ => Ok(bincode::serialize(self)?),
+        }
+    }
+
+    /// Decodes an action produced by [`ContextAction::to_bytes`].
+    ///
+    /// Tags above [`LAST_KNOWN_TAG`] decode to `Unknown`, and trailing fields added to a
+    /// known variant by a newer writer are ignored.
+    pub fn from_bytes(raw: &[u8]) -> Result<Self> {
+        if raw.len() < TAG_LEN {
+            return Err(anyhow!("Action record too short: {} bytes", raw.len()));
+        }
+        let mut tag = [0_u8; TAG_LEN];
+        tag.copy_from_slice(&raw[..TAG_LEN]);
+        let tag = u32::from_le_bytes(tag);
+        if tag > LAST_KNOWN_TAG {
+            return Ok(ContextAction::Unknown { tag, raw: raw.to_vec() });
+        }
+        Ok(bincode::deserialize(raw)?)
+    }
+}
+
+/// An action with the flags the node sent it with
+///
+/// `record` tells whether the action belongs in a recording, `perform` whether it is
+/// applied to the context storage when the recording is replayed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct ContextActionMessage {
     pub action: ContextAction,
     pub record: bool,
     pub perform: bool,
 }
 
-fn get_time(action: &ContextAction) -> f64 {
-    match action {
-        ContextAction::Set {
-            start_time,
-            end_time,
-            ..
-        } => *end_time - *start_time,
-        ContextAction::Delete {
-            start_time,
-            end_time,
-            ..
-        } => *end_time - *start_time,
-        ContextAction::RemoveRecursively {
-            start_time,
-            end_time,
-            ..
-        } => *end_time - *start_time,
-        ContextAction::Copy {
-            start_time,
-            end_time,
-            ..
-        } => *end_time - *start_time,
-        ContextAction::Checkout {
-            start_time,
-            end_time,
-            ..
-        } => *end_time - *start_time,
-        ContextAction::Commit {
-            start_time,
-            end_time,
-            ..
-        } => *end_time - *start_time,
-        ContextAction::Mem {
-            start_time,
-            end_time,
-            ..
-        } => *end_time - *start_time,
-        ContextAction::DirMem {
-            start_time,
-            end_time,
-            ..
-        } => *end_time - *start_time,
-        ContextAction::Get {
-            start_time,
-            end_time,
-            ..
-        } => *end_time - *start_time,
-        ContextAction::Fold {
-            start_time,
-            end_time,
-            ..
-        } => *end_time - *start_time,
-        ContextAction::Shutdown => 0_f64,
-    }
-}
-
-impl Ord for ContextAction {
+impl ContextActionMessage {
+    /// Tells the consumer of a channel to stop, neither recorded nor performed
+    pub fn shutdown() -> Self {
+        ContextActionMessage { action: ContextAction::Shutdown, record: false, perform: false }
+    }
+}
+
+/// Selects messages by their `record` and `perform` flags, `None` matches either value
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FlagFilter {
+    pub record: Option<bool>,
+    pub perform: Option<bool>,
+}
+
+impl FlagFilter {
+    /// Matches every message
+    pub const ANY: FlagFilter = FlagFilter { record: None, perform: None };
+
+    /// Matches messages with `record` set
+    pub fn recorded() -> Self {
+        FlagFilter { record: Some(true), perform: None }
+    }
+
+    /// Matches messages with `perform` set
+    pub fn performed() -> Self {
+        FlagFilter { record: None, perform: Some(true) }
+    }
+
+    pub fn matches_flags(&self, record: bool, perform: bool) -> bool {
+        self.record.map(|wanted| wanted == record).unwrap_or(true)
+            && self.perform.map(|wanted| wanted == perform).unwrap_or(true)
+    }
+
+    pub fn matches(&self, msg: &ContextActionMessage) -> bool {
+        self.matches_flags(msg.record, msg.perform)
+    }
+}
+
+/// Action as returned by the node RPC `/dev/chains/main/actions/blocks/{hash}`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ContextActionJson {
+    pub action: ContextAction,
+}
+
+/// Actions served by the node RPC were recorded and performed.
+impl From<ContextActionJson> for ContextActionMessage {
+    fn from(action_json: ContextActionJson) -> Self {
+        ContextActionMessage {
+            action: action_json.action,
+            record: true,
+            perform: true,
+        }
+    }
+}
+
+/// Block as returned by the node RPC `/dev/chains/main/blocks`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlockJson {
+    pub hash: BlockHash,
+    pub header: BlockHeaderJson,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlockHeaderJson {
+    pub level: u32,
+    pub predecessor: BlockHash,
+}
+
+impl From<BlockJson> for Block {
+    fn from(block: BlockJson) -> Self {
+        Block {
+            block_level: block.header.level,
+            block_hash: block.hash,
+            predecessor: block.header.predecessor,
+        }
+    }
+}
+
+/// Kind of a `ContextAction`, without its payload.
+///
+/// Displays and parses as the snake case name of the variant, e.g. `set` or `dir_mem`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ActionKind {
+    Set,
+    Delete,
+    RemoveRecursively,
+    Copy,
+    Checkout,
+    Commit,
+    Mem,
+    DirMem,
+    Get,
+    Fold,
+    Find,
+    FindTree,
+    AddTree,
+    List,
+    Hash,
+    Unknown,
+    Shutdown,
+}
+
+impl ActionKind {
+    pub const ALL: [ActionKind; 17] = [
+        ActionKind::Set,
+        ActionKind::Delete,
+        ActionKind::RemoveRecursively,
+        ActionKind::Copy,
+        ActionKind::Checkout,
+        ActionKind::Commit,
+        ActionKind::Mem,
+        ActionKind::DirMem,
+        ActionKind::Get,
+        ActionKind::Fold,
+        ActionKind::Find,
+        ActionKind::FindTree,
+        ActionKind::AddTree,
+        ActionKind::List,
+        ActionKind::Hash,
+        ActionKind::Unknown,
+        ActionKind::Shutdown,
+    ];
+
+    /// Kind of an encoded action with this tag, tags above `LAST_KNOWN_TAG` decode to `Unknown`
+    pub fn from_tag(tag: u32) -> ActionKind {
+        if tag <= LAST_KNOWN_TAG {
+            ActionKind::ALL[tag as usize]
+        } else {
+            ActionKind::Unknown
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ActionKind::Set => "set",
+            ActionKind::Delete => "delete",
+            ActionKind::RemoveRecursively => "remove_recursively",
+            ActionKind::Copy => "copy",
+            ActionKind::Checkout => "checkout",
+            ActionKind::Commit => "commit",
+            ActionKind::Mem => "mem",
+            ActionKind::DirMem => "dir_mem",
+            ActionKind::Get => "get",
+            ActionKind::Fold => "fold",
+            ActionKind::Find => "find",
+            ActionKind::FindTree => "find_tree",
+            ActionKind::AddTree => "add_tree",
+            ActionKind::List => "list",
+            ActionKind::Hash => "hash",
+            ActionKind::Unknown => "unknown",
+            ActionKind::Shutdown => "shutdown",
+        }
+    }
+}
+
+impl fmt::Display for ActionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ActionKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        ActionKind::ALL
+            .iter()
+            .find(|kind| kind.as_str().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| anyhow!("Unknown action kind: {}", s))
+    }
+}
+
+impl ContextAction {
+    pub fn kind(&self) -> ActionKind {
+        match self {
+            ContextAction::Set { .. } => ActionKind::Set,
+            ContextAction::Delete { .. } => ActionKind::Delete,
+            ContextAction::RemoveRecursively { .. } => ActionKind::RemoveRecursively,
+            ContextAction::Copy { .. } => ActionKind::Copy,
+            ContextAction::Checkout { .. } => ActionKind::Checkout,
+            ContextAction::Commit { .. } => ActionKind::Commit,
+            ContextAction::Mem { .. } => ActionKind::Mem,
+            ContextAction::DirMem { .. } => ActionKind::DirMem,
+            ContextAction::Get { .. } => ActionKind::Get,
+            ContextAction::Fold { .. } => ActionKind::Fold,
+            ContextAction::Find { .. } => ActionKind::Find,
+            ContextAction::FindTree { .. } => ActionKind::FindTree,
+            ContextAction::AddTree { .. } => ActionKind::AddTree,
+            ContextAction::List { .. } => ActionKind::List,
+            ContextAction::Hash { .. } => ActionKind::Hash,
+            ContextAction::Unknown { .. } => ActionKind::Unknown,
+            ContextAction::Shutdown => ActionKind::Shutdown,
+        }
+    }
+
+    /// Returns the key the action operates on, `to_key` for `Copy`.
+    pub fn key(&self) -> Option<&[String]> {
+        match self {
+            ContextAction::Set { key, .. }
+            | ContextAction::Delete { key, .. }
+            | ContextAction::RemoveRecursively { key, .. }
+            | ContextAction::Copy { to_key: key, .. }
+            | ContextAction::Mem { key, .. }
+            | ContextAction::DirMem { key, .. }
+            | ContextAction::Get { key, .. }
+            | ContextAction::Fold { key, .. }
+            | ContextAction::Find { key, .. }
+            | ContextAction::FindTree { key, .. }
+            | ContextAction::AddTree { key, .. }
+            | ContextAction::List { key, .. }
+            | ContextAction::Hash { key, .. } => Some(key),
+            ContextAction::Checkout { .. }
+            | ContextAction::Commit { .. }
+            | ContextAction::Unknown { .. }
+            | ContextAction::Shutdown => None,
+        }
+    }
+
+    /// Returns the context the action was applied to, `parent_context_hash` for `Commit`.
+    pub fn context_hash(&self) -> Option<&Hash> {
+        match self {
+            ContextAction::Set { context_hash, .. }
+            | ContextAction::Delete { context_hash, .. }
+            | ContextAction::RemoveRecursively { context_hash, .. }
+            | ContextAction::Copy { context_hash, .. }
+            | ContextAction::Mem { context_hash, .. }
+            | ContextAction::DirMem { context_hash, .. }
+            | ContextAction::Get { context_hash, .. }
+            | ContextAction::Fold { context_hash, .. }
+            | ContextAction::Find { context_hash, .. }
+            | ContextAction::FindTree { context_hash, .. }
+            | ContextAction::AddTree { context_hash, .. }
+            | ContextAction::List { context_hash, .. }
+            | ContextAction::Hash { context_hash, .. }
+            | ContextAction::Commit { parent_context_hash: context_hash, .. } => context_hash.as_ref(),
+            ContextAction::Checkout { context_hash, .. } => Some(context_hash),
+            ContextAction::Unknown { .. } | ContextAction::Shutdown => None,
+        }
+    }
+
+    pub fn block_hash(&self) -> Option<&Hash> {
+        match self {
+            ContextAction::Set { block_hash, .. }
+            | ContextAction::Delete { block_hash, .. }
+            | ContextAction::RemoveRecursively { block_hash, .. }
+            | ContextAction::Copy { block_hash, .. }
+            | ContextAction::Commit { block_hash, .. }
+            | ContextAction::Mem { block_hash, .. }
+            | ContextAction::DirMem { block_hash, .. }
+            | ContextAction::Get { block_hash, .. }
+            | ContextAction::Fold { block_hash, .. }
+            | ContextAction::Find { block_hash, .. }
+            | ContextAction::FindTree { block_hash, .. }
+            | ContextAction::AddTree { block_hash, .. }
+            | ContextAction::List { block_hash, .. }
+            | ContextAction::Hash { block_hash, .. } => block_hash.as_ref(),
+            ContextAction::Checkout { .. } | ContextAction::Unknown { .. } | ContextAction::Shutdown => None,
+        }
+    }
+
+    pub fn operation_hash(&self) -> Option<&Hash> {
+        match self {
+            ContextAction::Set { operation_hash, .. }
+            | ContextAction::Delete { operation_hash, .. }
+            | ContextAction::RemoveRecursively { operation_hash, .. }
+            | ContextAction::Copy { operation_hash, .. }
+            | ContextAction::Mem { operation_hash, .. }
+            | ContextAction::DirMem { operation_hash, .. }
+            | ContextAction::Get { operation_hash, .. }
+            | ContextAction::Fold { operation_hash, .. }
+            | ContextAction::Find { operation_hash, .. }
+            | ContextAction::FindTree { operation_hash, .. }
+            | ContextAction::AddTree { operation_hash, .. }
+            | ContextAction::List { operation_hash, .. }
+            | ContextAction::Hash { operation_hash, .. } => operation_hash.as_ref(),
+            ContextAction::Checkout { .. }
+            | ContextAction::Commit { .. }
+            | ContextAction::Unknown { .. }
+            | ContextAction::Shutdown => None,
+        }
+    }
+
+    /// Returns the hash of the tree the action was applied to.
+    pub fn tree_hash(&self) -> Option<&Hash> {
+        match self {
+            ContextAction::Set { tree_hash, .. }
+            | ContextAction::Delete { tree_hash, .. }
+            | ContextAction::RemoveRecursively { tree_hash, .. }
+            | ContextAction::Copy { tree_hash, .. }
+            | ContextAction::Commit { tree_hash, .. }
+            | ContextAction::Mem { tree_hash, .. }
+            | ContextAction::DirMem { tree_hash, .. }
+            | ContextAction::Get { tree_hash, .. }
+            | ContextAction::Fold { tree_hash, .. }
+            | ContextAction::Find { tree_hash, .. }
+            | ContextAction::FindTree { tree_hash, .. }
+            | ContextAction::AddTree { tree_hash, .. }
+            | ContextAction::List { tree_hash, .. }
+            | ContextAction::Hash { tree_hash, .. } => Some(tree_hash),
+            ContextAction::Checkout { .. } | ContextAction::Unknown { .. } | ContextAction::Shutdown => None,
+        }
+    }
+
+    /// Returns the hash of the tree produced by a write action.
+    pub fn new_tree_hash(&self) -> Option<&Hash> {
+        match self {
+            ContextAction::Set { new_tree_hash, .. }
+            | ContextAction::Delete { new_tree_hash, .. }
+            | ContextAction::RemoveRecursively { new_tree_hash, .. }
+            | ContextAction::Copy { new_tree_hash, .. }
+            | ContextAction::AddTree { new_tree_hash, .. } => Some(new_tree_hash),
+            _ => None,
+        }
+    }
+
+    /// Returns the size in bytes of the value written or read by the action.
+    pub fn value_size(&self) -> Option<usize> {
+        match self {
+            ContextAction::Set { value, .. } | ContextAction::Get { value, .. } => Some(value.len()),
+            ContextAction::Find { value, .. } => value.as_ref().map(|value| value.len()),
+            ContextAction::AddTree { value, .. } => Some(value.iter().map(|(_, leaf)| leaf.len()).sum()),
+            _ => None,
+        }
+    }
+
+    pub fn start_time(&self) -> Option<f64> {
+        self.times().map(|(start_time, _)| start_time)
+    }
+
+    pub fn end_time(&self) -> Option<f64> {
+        self.times().map(|(_, end_time)| end_time)
+    }
+
+    /// Returns `end_time - start_time`, or zero for actions without timings.
+    pub fn duration(&self) -> f64 {
+        self.times().map(|(start_time, end_time)| end_time - start_time).unwrap_or(0_f64)
+    }
+
+    /// Returns true for actions that modify the context.
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self.kind(),
+            ActionKind::Set
+                | ActionKind::Delete
+                | ActionKind::RemoveRecursively
+                | ActionKind::Copy
+                | ActionKind::AddTree
+                | ActionKind::Commit
+        )
+    }
+
+    /// Returns true for actions that only query the context.
+    pub fn is_read(&self) -> bool {
+        matches!(
+            self.kind(),
+            ActionKind::Mem
+                | ActionKind::DirMem
+                | ActionKind::Get
+                | ActionKind::Fold
+                | ActionKind::Find
+                | ActionKind::FindTree
+                | ActionKind::List
+                | ActionKind::Hash
+        )
+    }
+
+    fn times(&self) -> Option<(f64, f64)> {
+        match self {
+            ContextAction::Set { start_time, end_time, .. }
+            | ContextAction::Delete { start_time, end_time, .. }
+            | ContextAction::RemoveRecursively { start_time, end_time, .. }
+            | ContextAction::Copy { start_time, end_time, .. }
+            | ContextAction::Checkout { start_time, end_time, .. }
+            | ContextAction::Commit { start_time, end_time, .. }
+            | ContextAction::Mem { start_time, end_time, .. }
+            | ContextAction::DirMem { start_time, end_time, .. }
+            | ContextAction::Get { start_time, end_time, .. }
+            | ContextAction::Fold { start_time, end_time, .. }
+            | ContextAction::Find { start_time, end_time, .. }
+            | ContextAction::FindTree { start_time, end_time, .. }
+            | ContextAction::AddTree { start_time, end_time, .. }
+            | ContextAction::List { start_time, end_time, .. }
+            | ContextAction::Hash { start_time, end_time, .. } => Some((*start_time, *end_time)),
+            ContextAction::Unknown { .. } | ContextAction::Shutdown => None,
+        }
+    }
+}
+
+impl ContextAction {
+    /// Orders actions by their duration, for use with `sort_by` and friends.
+    pub fn by_duration(a: &ContextAction, b: &ContextAction) -> std::cmp::Ordering {
+        a.duration().partial_cmp(&b.duration()).unwrap_or(Equal)
+    }
+}
+
+/// Compares two actions of the same variant field by field, the fields after `;` by their bits
+///
+/// Every field of a variant has to be listed, so adding a field to `ContextAction` fails
+/// to compile until it is compared.
+macro_rules! eq_fields {
+    ($a:expr, $b:expr, $($variant:ident { $($field:ident),* $(; $($time:ident),*)? }),* $(,)?) => {
+        match ($a, $b) {
+            $(
+                (ContextAction::$variant { $($field,)* $($($time,)*)? }, ContextAction::$variant { .. }) => {
+                    let left = (($($field,)*), ($($($time.to_bits(),)*)?));
+                    match $b {
+                        ContextAction::$variant { $($field,)* $($($time,)*)? } => left == (($($field,)*), ($($($time.to_bits(),)*)?)),
+                        _ => false,
+                    }
+                }
+            )*
+            _ => false,
+        }
+    };
+}
+
+/// Structural equality, with timings compared by their bits
+///
+/// Timings decoded from files or JSON can be NaN, comparing bits keeps every action equal to
+/// itself, so `Eq` holds and actions can be deduplicated in a `HashSet`.
+impl PartialEq for ContextAction {
+    fn eq(&self, other: &Self) -> bool {
+        eq_fields!(self, other,
+            Set { context_hash, block_hash, operation_hash, tree_hash, new_tree_hash, key, value, value_as_json; start_time, end_time },
+            Delete { context_hash, block_hash, operation_hash, tree_hash, new_tree_hash, key; start_time, end_time },
+            RemoveRecursively { context_hash, block_hash, operation_hash, tree_hash, new_tree_hash, key; start_time, end_time },
+            Copy { context_hash, block_hash, operation_hash, tree_hash, new_tree_hash, from_key, to_key; start_time, end_time },
+            Checkout { context_hash; start_time, end_time },
+            Commit { parent_context_hash, block_hash, new_context_hash, tree_hash, author, message, date, parents; start_time, end_time },
+            Mem { context_hash, block_hash, operation_hash, tree_hash, key, value; start_time, end_time },
+            DirMem { context_hash, block_hash, operation_hash, tree_hash, key, value; start_time, end_time },
+            Get { context_hash, block_hash, operation_hash, tree_hash, key, value, value_as_json; start_time, end_time },
+            Fold { context_hash, block_hash, operation_hash, tree_hash, key; start_time, end_time },
+            Find { context_hash, block_hash, operation_hash, tree_hash, key, value; start_time, end_time },
+            FindTree { context_hash, block_hash, operation_hash, tree_hash, key, value; start_time, end_time },
+            AddTree { context_hash, block_hash, operation_hash, tree_hash, new_tree_hash, key, value; start_time, end_time },
+            List { context_hash, block_hash, operation_hash, tree_hash, key, offset, length, value; start_time, end_time },
+            Hash { context_hash, block_hash, operation_hash, tree_hash, key, value; start_time, end_time },
+            Unknown { tag, raw },
+            Shutdown {},
+        )
+    }
+}
+
+impl Eq for ContextAction {}
+
+/// Hashes the kind, keys and hashes of the action.
+///
+/// Timings and values are left out, which keeps the hash consistent with `PartialEq`.
+impl std::hash::Hash for ContextAction {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.kind().hash(state);
+        self.key().hash(state);
+        self.context_hash().hash(state);
+        self.block_hash().hash(state);
+        self.operation_hash().hash(state);
+        self.tree_hash().hash(state);
+        self.new_tree_hash().hash(state);
+        if let ContextAction::Unknown { tag, raw } = self {
+            tag.hash(state);
+            raw.hash(state);
+        }
+    }
+}
+
+/// Wrapper ordering actions by [`ContextAction::by_duration`], e.g. in a `BinaryHeap`.
+#[derive(Clone, Debug)]
+pub struct ByDuration(pub ContextAction);
+
+impl Ord for ByDuration {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        get_time(&self)
-            .partial_cmp(&get_time(&other))
-            .unwrap_or(Equal)
+        ContextAction::by_duration(&self.0, &other.0)
     }
 }
 
-impl PartialOrd for ContextAction {
+impl PartialOrd for ByDuration {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl PartialEq for ContextAction {
+impl PartialEq for ByDuration {
     fn eq(&self, other: &Self) -> bool {
-        get_time(&self) == get_time(&other)
+        self.cmp(other) == Equal
     }
 }
 
-impl Eq for ContextAction {}
+impl Eq for ByDuration {}
+
+/// Recorded and performed checkout of `hash` repeated, shared by the tests of the crate
+#[cfg(test)]
+pub(crate) fn msg(hash: u8) -> ContextActionMessage {
+    ContextActionMessage {
+        action: ContextAction::Checkout { context_hash: vec![hash; 32], start_time: 0.0, end_time: 1.0 },
+        record: true,
+        perform: true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_matches_encoding() {
+        let actions = vec![
+            ContextAction::Checkout { context_hash: vec![1; 32], start_time: 0.0, end_time: 1.0 },
+            ContextAction::Fold {
+                context_hash: None,
+                block_hash: None,
+                operation_hash: None,
+                tree_hash: vec![],
+                start_time: 0.0,
+                end_time: 1.0,
+                key: vec!["data".to_string()],
+            },
+            ContextAction::List {
+                context_hash: None,
+                block_hash: None,
+                operation_hash: None,
+                tree_hash: vec![],
+                start_time: 0.0,
+                end_time: 1.0,
+                key: vec!["data".to_string()],
+                offset: Some(1),
+                length: None,
+                value: vec!["contracts".to_string()],
+            },
+        ];
+        for action in actions {
+            let raw = action.to_bytes().unwrap();
+            assert_eq!(u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]), action.tag());
+            assert_eq!(ContextAction::from_bytes(&raw).unwrap().tag(), action.tag());
+            assert_eq!(ActionKind::from_tag(action.tag()), action.kind());
+        }
+    }
+
+    #[test]
+    fn test_action_kind_names() {
+        for kind in ActionKind::ALL.iter() {
+            assert_eq!(kind.to_string().parse::<ActionKind>().unwrap(), *kind);
+        }
+        assert_eq!("DIR_MEM".parse::<ActionKind>().unwrap(), ActionKind::DirMem);
+        assert!("nope".parse::<ActionKind>().is_err());
+
+        let action = ContextAction::Copy {
+            context_hash: None,
+            block_hash: Some(vec![1]),
+            operation_hash: None,
+            tree_hash: vec![2],
+            new_tree_hash: vec![3],
+            start_time: 1.0,
+            end_time: 3.5,
+            from_key: vec!["a".to_string()],
+            to_key: vec!["b".to_string()],
+        };
+        assert_eq!(action.kind(), ActionKind::Copy);
+        assert_eq!(action.key(), Some(&["b".to_string()][..]));
+        assert_eq!(action.new_tree_hash(), Some(&vec![3]));
+        assert_eq!(action.duration(), 2.5);
+        assert!(action.is_write() && !action.is_read());
+    }
+
+    #[test]
+    fn test_structural_equality() {
+        let checkout = ContextAction::Checkout { context_hash: vec![1], start_time: 0.0, end_time: 1.0 };
+        let other = ContextAction::Checkout { context_hash: vec![2], start_time: 5.0, end_time: 6.0 };
+        assert_ne!(checkout, other);
+        assert_eq!(ContextAction::by_duration(&checkout, &other), Equal);
+
+        let set: std::collections::HashSet<_> = vec![checkout.clone(), checkout.clone(), other].into_iter().collect();
+        assert_eq!(set.len(), 2);
+
+        let nan = ContextAction::Checkout { context_hash: vec![1], start_time: f64::NAN, end_time: 1.0 };
+        assert_eq!(nan, nan.clone());
+        assert_ne!(nan, checkout);
+        let set: std::collections::HashSet<_> = vec![nan.clone(), nan].into_iter().collect();
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn test_channels_are_independent() {
+        let first = ActionChannel::new(4);
+        let second = first.clone();
+        let other = ActionChannel::new(4);
+        second.send(msg(1)).unwrap();
+        other.send(msg(2)).unwrap();
+        assert_eq!(first.receive().unwrap(), msg(1));
+        assert!(first.is_empty());
+        assert_eq!(other.len(), 1);
+
+        other.disable();
+        other.send(msg(3)).unwrap();
+        assert_eq!(other.len(), 1);
+        assert_eq!(other.capacity(), 4);
+    }
+
+    #[test]
+    fn test_overflow_policies() {
+        let channel = ActionChannel::with_policy(2, OverflowPolicy::DropNewest);
+        (1..=3).for_each(|hash| channel.send(msg(hash)).unwrap());
+        assert_eq!(channel.receive().unwrap(), msg(1));
+        assert_eq!(channel.metrics(), ChannelMetrics { sent: 2, dropped: 1, discarded: 0, high_water: 2 });
+
+        let channel = ActionChannel::with_policy(2, OverflowPolicy::DropOldest);
+        (1..=3).for_each(|hash| channel.send(msg(hash)).unwrap());
+        assert_eq!(channel.receive().unwrap(), msg(2));
+        assert_eq!(channel.receive().unwrap(), msg(3));
+        assert_eq!(channel.metrics().dropped, 1);
+
+        channel.set_policy(OverflowPolicy::Timeout(Duration::from_millis(10)));
+        (1..=2).for_each(|hash| channel.send(msg(hash)).unwrap());
+        match channel.send(msg(3)) {
+            Err(SendTimeoutError::Timeout(rejected)) => assert_eq!(rejected, msg(3)),
+            other => panic!("unexpected {:?}", other),
+        }
+        channel.disable();
+        channel.send(msg(4)).unwrap();
+        assert_eq!(channel.metrics(), ChannelMetrics { sent: 5, dropped: 2, discarded: 1, high_water: 2 });
+    }
+
+    #[test]
+    fn test_drop_oldest_keeps_shutdown() {
+        let channel = ActionChannel::with_policy(2, OverflowPolicy::DropOldest);
+        channel.send(msg(1)).unwrap();
+        let waiting = {
+            let channel = channel.clone();
+            std::thread::spawn(move || channel.shutdown())
+        };
+        while channel.len() < 2 {
+            std::thread::yield_now();
+        }
+        (2..=4).for_each(|hash| channel.send(msg(hash)).unwrap());
+        assert_eq!(channel.iter().collect::<Vec<_>>(), vec![msg(1)]);
+        waiting.join().unwrap();
+        assert_eq!(channel.metrics().dropped, 3);
+    }
+
+    #[test]
+    fn test_receive_and_shutdown() {
+        let channel = ActionChannel::new(16);
+        let _producer = channel.clone();
+        assert_eq!(channel.try_receive(), Err(TryRecvError::Empty));
+        assert_eq!(channel.receive_timeout(Duration::from_millis(1)), Err(RecvTimeoutError::Timeout));
+        (1..=3).for_each(|hash| channel.send(msg(hash)).unwrap());
+        assert_eq!(channel.receive_many(2).unwrap(), vec![msg(1), msg(2)]);
+        assert_eq!(channel.receive_many(8).unwrap(), vec![msg(3)]);
+        assert!(!channel.shutdown_timeout(Duration::from_millis(1)));
+        assert_eq!(channel.iter().count(), 0);
+
+        (1..=3).for_each(|hash| channel.send(msg(hash)).unwrap());
+        let consumer = {
+            let channel = channel.clone();
+            std::thread::spawn(move || channel.iter().collect::<Vec<_>>())
+        };
+        channel.shutdown();
+        assert_eq!(consumer.join().unwrap(), vec![msg(1), msg(2), msg(3)]);
+        assert!(channel.is_empty());
+
+        let full = ActionChannel::new(1);
+        full.send(msg(1)).unwrap();
+        assert!(!full.shutdown_timeout(Duration::from_millis(10)));
+        assert_eq!(full.receive_many(2).unwrap(), vec![msg(1)]);
+    }
+
+    #[test]
+    fn test_other_side_going_away() {
+        let consumer = ActionChannel::new(1);
+        let producer = consumer.clone();
+        let receiving = {
+            let consumer = consumer.clone();
+            std::thread::spawn(move || consumer.iter().collect::<Vec<_>>())
+        };
+        producer.send(msg(1)).unwrap();
+        drop(producer);
+        drop(consumer);
+        assert_eq!(receiving.join().unwrap(), vec![msg(1)]);
+
+        let consumer = ActionChannel::new(1);
+        let producer = consumer.clone();
+        assert_eq!(consumer.receive_timeout(Duration::from_millis(1)), Err(RecvTimeoutError::Timeout));
+        producer.send(msg(1)).unwrap();
+        let blocked = std::thread::spawn(move || producer.send(msg(2)).map_err(|e| e.is_disconnected()));
+        consumer.close();
+        assert_eq!(blocked.join().unwrap(), Err(true));
+        assert_eq!(consumer.try_receive(), Ok(msg(1)));
+        assert_eq!(consumer.try_receive(), Err(TryRecvError::Disconnected));
+        assert_eq!(consumer.receive(), Err(RecvError));
+    }
+}
@@ -3,96 +3,184 @@ use bytes::{BytesMut, BufMut, Buf};
 use std::fmt::Formatter;
 use std::path::Path;
 use std::fs::{File, OpenOptions};
-use crate::channel::{ContextAction, ContextActionMessage};
+use crate::channel::{ActionKind, ContextAction, ContextActionMessage, FlagFilter};
+use crate::filter::ActionFilter;
+use crate::hash::{BlockHash, HASH_LEN};
 use anyhow::Result;
 use anyhow::anyhow;
-use cluFlock::{ToFlock, FlockLock};
 
 use serde::{Serialize, Deserialize};
 
-type Hash = Vec<u8>;
+/// Start of the header of files with a format version
+///
+/// Files without it are legacy files, whose header starts with the block height. A legacy
+/// file would need to reach level 1_415_201_094 to start with these bytes.
+const MAGIC: [u8; 4] = *b"TZAF";
+
+/// Format version written by `ActionsFileWriter`
+///
+/// 0. Legacy files: no magic, `(Block, Vec<ContextActionMessage>)` records with the hex block hash
+/// 1. Magic and version in the header, the level of each block in front of its record,
+///    records of `(Block, Vec<ActionRecord>)`
+pub const FORMAT_VERSION: u32 = 1;
 
-const HEADER_LEN: usize = 44;
-const BLOCK_HASH_HEADER_LEN: usize = 32;
+const LEGACY_HEADER_LEN: usize = 44;
+const HEADER_LEN: usize = MAGIC.len() + 4 + LEGACY_HEADER_LEN;
+const BLOCK_HASH_HEADER_LEN: usize = HASH_LEN;
 
+/// Block of an actions file
+///
+/// Blocks are serialized at the start of every block record, so a change to the fields is a
+/// new [`FORMAT_VERSION`]. Format version 0 also stored the hex hash, see `LegacyBlock`.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Block {
     pub block_level: u32,
-    pub block_hash_hex: String,
-    pub block_hash: [u8; BLOCK_HASH_HEADER_LEN],
-    pub predecessor: [u8; BLOCK_HASH_HEADER_LEN],
+    pub block_hash: BlockHash,
+    pub predecessor: BlockHash,
 }
 
 impl Block {
     pub fn new(block_level: u32, raw_block_hash: Vec<u8>, raw_predecessor: Vec<u8>) -> Self {
-        let block_hash_hex = hex::encode(&raw_block_hash);
         let mut predecessor = [0_u8; BLOCK_HASH_HEADER_LEN];
         copy_hash_to_slice(raw_predecessor, &mut predecessor);
         let mut block_hash = [0_u8; BLOCK_HASH_HEADER_LEN];
         copy_hash_to_slice(raw_block_hash, &mut block_hash);
         Block {
             block_level,
-            block_hash_hex,
-            predecessor,
-            block_hash,
+            predecessor: BlockHash::new(predecessor),
+            block_hash: BlockHash::new(block_hash),
+        }
+    }
+}
+
+/// `Block` as written by format version 0
+///
+/// The hex hash is read past and dropped, `block_hash` holds the same hash.
+#[derive(Deserialize)]
+struct LegacyBlock {
+    block_level: u32,
+    #[allow(dead_code)]
+    block_hash_hex: String,
+    block_hash: [u8; BLOCK_HASH_HEADER_LEN],
+    predecessor: [u8; BLOCK_HASH_HEADER_LEN],
+}
+
+impl From<LegacyBlock> for Block {
+    fn from(block: LegacyBlock) -> Self {
+        Block {
+            block_level: block.block_level,
+            block_hash: BlockHash::new(block.block_hash),
+            predecessor: BlockHash::new(block.predecessor),
         }
     }
 }
 
+/// On-disk form of a `ContextActionMessage`.
+///
+/// The action is stored as its length-delimited versioned encoding so that readers can
+/// skip variants they do not know instead of failing on the whole block.
+#[derive(Serialize)]
+struct ActionRecord {
+    record: bool,
+    perform: bool,
+    action: Vec<u8>,
+}
+
+impl ActionRecord {
+    fn encode(msg: &ContextActionMessage) -> Result<Self> {
+        Ok(ActionRecord {
+            record: msg.record,
+            perform: msg.perform,
+            action: msg.action.to_bytes()?,
+        })
+    }
+}
+
+/// `ActionRecord` borrowing the encoded action from the decompressed block record
+#[derive(Deserialize)]
+struct RawActionRecord<'a> {
+    record: bool,
+    perform: bool,
+    #[serde(borrow)]
+    action: &'a [u8],
+}
+
+impl RawActionRecord<'_> {
+    /// Kind of the action, read from its tag without decoding it
+    fn kind(&self) -> ActionKind {
+        match self.action.get(..4) {
+            Some(tag) => ActionKind::from_tag(u32::from_le_bytes([tag[0], tag[1], tag[2], tag[3]])),
+            None => ActionKind::Unknown,
+        }
+    }
+
+    fn decode(&self) -> Result<ContextActionMessage> {
+        Ok(ContextActionMessage {
+            action: ContextAction::from_bytes(self.action)?,
+            record: self.record,
+            perform: self.perform,
+        })
+    }
+}
+
 fn copy_hash_to_slice(from: Vec<u8>, to: &mut [u8; BLOCK_HASH_HEADER_LEN]) {
-    let mut bytes = BytesMut::with_capacity(BLOCK_HASH_HEADER_LEN);
-    bytes.put_slice(&from);
-    bytes.reader().read_exact(to);
+    let len = from.len().min(BLOCK_HASH_HEADER_LEN);
+    to[..len].copy_from_slice(&from[..len]);
 }
 
 
 #[derive(Clone, Copy, Debug)]
 pub struct ActionsFileHeader {
-    pub current_block_hash: [u8; BLOCK_HASH_HEADER_LEN],
+    pub current_block_hash: BlockHash,
     pub block_height: u32,
     pub actions_count: u32,
     pub block_count: u32,
+    /// Format version of the file, `0` for legacy files
+    pub version: u32,
 }
 
 impl std::fmt::Display for ActionsFileHeader {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let mut formatter: String = String::new();
-        let block_hash_hex = merkle::prelude::HashType::BlockHash.hash_to_b58check(&self.current_block_hash);
-        formatter.push_str(&format!("{:<24}{}\n", "Block Hash:", block_hash_hex));
+        formatter.push_str(&format!("{:<24}{}\n", "Block Hash:", self.current_block_hash));
         formatter.push_str(&format!("{:<24}{}\n", "Block Height:", self.block_height));
         formatter.push_str(&format!("{:<24}{}\n", "Block Count:", self.block_count));
-        formatter.push_str(&format!("{:<24}{}", "Actions Count:", self.actions_count));
+        formatter.push_str(&format!("{:<24}{}\n", "Actions Count:", self.actions_count));
+        formatter.push_str(&format!("{:<24}{}", "Format Version:", self.version));
         writeln!(f, "{}", formatter)
     }
 }
 
 
-impl From<[u8; HEADER_LEN]> for ActionsFileHeader {
-    fn from(v: [u8; HEADER_LEN]) -> Self {
+impl From<[u8; LEGACY_HEADER_LEN]> for ActionsFileHeader {
+    fn from(v: [u8; LEGACY_HEADER_LEN]) -> Self {
         let mut bytes = BytesMut::with_capacity(v.len());
         bytes.put_slice(&v);
         let block_height = bytes.get_u32();
         let actions_count = bytes.get_u32();
         let block_count = bytes.get_u32();
         let mut hash = [0_u8; BLOCK_HASH_HEADER_LEN];
-        bytes.reader().read_exact(&mut hash);
+        bytes.copy_to_slice(&mut hash);
 
         ActionsFileHeader {
             block_height,
             actions_count,
             block_count,
-            current_block_hash: hash,
+            current_block_hash: BlockHash::new(hash),
+            version: 0,
         }
     }
 }
 
 impl ActionsFileHeader {
-    fn to_vec(&self) -> Vec<u8> {
+    fn to_vec(self) -> Vec<u8> {
         let mut bytes = BytesMut::with_capacity(HEADER_LEN);
+        bytes.put_slice(&MAGIC);
+        bytes.put_u32(self.version);
         bytes.put_u32(self.block_height);
         bytes.put_u32(self.actions_count);
         bytes.put_u32(self.block_count);
-        bytes.put_slice(&self.current_block_hash);
+        bytes.put_slice(self.current_block_hash.as_ref());
         bytes.to_vec()
     }
     fn new() -> Self {
@@ -100,82 +188,295 @@ impl ActionsFileHeader {
             block_height: 0,
             actions_count: 0,
             block_count: 0,
-            current_block_hash: [0_u8; BLOCK_HASH_HEADER_LEN],
+            current_block_hash: BlockHash::default(),
+            version: FORMAT_VERSION,
+        }
+    }
+
+    /// Reads the header at the start of `reader`, an empty file has a new header
+    fn read_from<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        let mut bytes = vec![];
+        reader.take(HEADER_LEN as u64).read_to_end(&mut bytes)?;
+        if bytes.is_empty() {
+            return Ok(ActionsFileHeader::new());
         }
+        if bytes.starts_with(&MAGIC) {
+            if bytes.len() < HEADER_LEN {
+                return Err(anyhow!("Truncated actions file header"));
+            }
+            let version = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+            if version == 0 || version > FORMAT_VERSION {
+                return Err(anyhow!("Unsupported actions file format version {}, this version reads up to {}", version, FORMAT_VERSION));
+            }
+            let mut legacy = [0_u8; LEGACY_HEADER_LEN];
+            legacy.copy_from_slice(&bytes[MAGIC.len() + 4..]);
+            return Ok(ActionsFileHeader { version, ..ActionsFileHeader::from(legacy) });
+        }
+        if bytes.len() < LEGACY_HEADER_LEN {
+            return Err(anyhow!("Truncated actions file header"));
+        }
+        let mut legacy = [0_u8; LEGACY_HEADER_LEN];
+        legacy.copy_from_slice(&bytes[..LEGACY_HEADER_LEN]);
+        Ok(ActionsFileHeader::from(legacy))
+    }
+
+    /// Length of the header, where the first block record starts
+    fn len(&self) -> u64 {
+        if self.version == 0 { LEGACY_HEADER_LEN as u64 } else { HEADER_LEN as u64 }
+    }
+
+    /// Whether block records carry their level in front of the compressed record
+    fn has_levels(&self) -> bool {
+        self.version >= 1
     }
 }
 
+/// Length and level of a block record, read from the frame in front of it
+struct Frame {
+    offset: u64,
+    /// Offset of the compressed record
+    start: u64,
+    len: u32,
+    /// `None` in legacy files
+    level: Option<u32>,
+}
+
+impl Frame {
+    /// Offset of the next record
+    fn end(&self) -> u64 {
+        self.start + u64::from(self.len)
+    }
+}
+
+/// Compressed block record and where it is in the file
+struct RawRecord {
+    offset: u64,
+    data: BytesMut,
+}
+
 pub struct ActionsFileReader {
     header: ActionsFileHeader,
     cursor: u64,
     reader: BufReader<File>,
+    filter: ActionFilter,
+    /// Level to move to by scanning the record frames before reading the next block
+    seek_level: Option<u32>,
+    /// Set once a record failed to decode, iteration ends after the error
+    failed: bool,
 }
 
 
 impl ActionsFileReader {
+    /// Opens an actions file of any format version up to [`FORMAT_VERSION`]
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let mut file = OpenOptions::new().write(false).create(false).read(true).open(path)?;
+        let file = OpenOptions::new().write(false).create(false).read(true).open(path)?;
+        Self::from_file(file)
+    }
+
+    fn from_file(file: File) -> Result<Self> {
         let mut reader = BufReader::new(file);
-        reader.seek(SeekFrom::Start(0));
-        let mut h = [0_u8; HEADER_LEN];
-        reader.read_exact(&mut h);
-        let header = ActionsFileHeader::from(h);
+        let header = ActionsFileHeader::read_from(&mut reader)?;
         Ok(ActionsFileReader {
             reader,
             header,
-            cursor: HEADER_LEN as u64,
+            cursor: header.len(),
+            filter: ActionFilter::default(),
+            seek_level: None,
+            failed: false,
         })
     }
 
+    /// Yields only the blocks and actions selected by `filter`
+    ///
+    /// Blocks are yielded even if none of their actions match, and iteration ends after
+    /// the last block of the level range.
+    pub fn with_filter(mut self, filter: ActionFilter) -> Self {
+        self.seek_level = filter.from_level.filter(|level| *level > 0);
+        self.filter = filter;
+        self
+    }
+
+    /// Yields only the actions whose `record` and `perform` flags match `flags`
+    ///
+    /// Blocks are yielded even if none of their actions match.
+    pub fn with_flags(mut self, flags: FlagFilter) -> Self {
+        self.filter.flags = flags;
+        self
+    }
+
     /// Prints header `ActionsFileHeader`
     pub fn header(&self) -> ActionsFileHeader {
         self.header
     }
 
-    pub fn fetch_header(&mut self) -> ActionsFileHeader {
-        self.reader.seek(SeekFrom::Start(0));
-        let mut h = [0_u8; HEADER_LEN];
-        self.reader.read_exact(&mut h);
-        self.header = ActionsFileHeader::from(h);
-        self.header()
+    pub fn fetch_header(&mut self) -> Result<ActionsFileHeader> {
+        self.header = ActionsFileHeader::read_from(&mut self.reader)?;
+        Ok(self.header())
+    }
+
+    /// Reads the block and position of every record without decoding their actions
+    ///
+    /// The reader is rewound to the first block afterwards.
+    pub fn index(&mut self) -> Result<Vec<BlockEntry>> {
+        self.cursor = self.header.len();
+        let mut index = vec![];
+        while let Some(record) = self.next_record()? {
+            let reader = snap::read::FrameDecoder::new(record.data.reader());
+            let (block, actions_count) = if self.header.has_levels() {
+                bincode::deserialize_from::<_, (Block, u64)>(reader)?
+            } else {
+                let (block, actions_count) = bincode::deserialize_from::<_, (LegacyBlock, u64)>(reader)?;
+                (block.into(), actions_count)
+            };
+            index.push(BlockEntry {
+                block,
+                offset: record.offset,
+                actions_count: actions_count as u32,
+            });
+        }
+        self.cursor = self.header.len();
+        Ok(index)
+    }
+
+    /// Reads the frame of the record at the cursor, leaving the file positioned at the compressed record
+    ///
+    /// Returns `None` at the end of the file and fails on a truncated frame.
+    fn next_frame(&mut self) -> Result<Option<Frame>> {
+        let offset = self.cursor;
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let mut h = [0_u8; 4];
+        match self.reader.read(&mut h[..1])? {
+            0 => return Ok(None),
+            _ => self.reader.read_exact(&mut h[1..]).map_err(|_| anyhow!("Truncated record at offset {}", offset))?,
+        }
+        let len = u32::from_be_bytes(h);
+        if len == 0 {
+            return Err(anyhow!("Empty record at offset {}", offset));
+        }
+        let level = if self.header.has_levels() {
+            self.reader.read_exact(&mut h).map_err(|_| anyhow!("Truncated record at offset {}", offset))?;
+            Some(u32::from_be_bytes(h))
+        } else {
+            None
+        };
+        let start = self.reader.stream_position()?;
+        Ok(Some(Frame { offset, start, len, level }))
+    }
+
+    /// Reads the compressed record at the cursor and moves the cursor past it
+    ///
+    /// Returns `None` at the end of the file and fails on a truncated record.
+    fn next_record(&mut self) -> Result<Option<RawRecord>> {
+        let frame = match self.next_frame()? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let mut data = BytesMut::new();
+        data.resize(frame.len as usize, 0);
+        self.reader.read_exact(&mut data).map_err(|_| anyhow!("Truncated record at offset {}", frame.offset))?;
+        self.cursor = frame.end();
+        Ok(Some(RawRecord { offset: frame.offset, data }))
+    }
+
+    /// Moves the cursor to the first block at or above `level`, past the last block if there is none
+    ///
+    /// Records before it are skipped by their frame. Legacy frames have no level, so only the
+    /// block at the start of each of their records is decompressed, never the actions.
+    fn seek(&mut self, level: u32) -> Result<()> {
+        self.cursor = self.header.len();
+        while let Some(frame) = self.next_frame()? {
+            let record_level = match frame.level {
+                Some(record_level) => record_level,
+                None => {
+                    let reader = snap::read::FrameDecoder::new((&mut self.reader).take(u64::from(frame.len)));
+                    bincode::deserialize_from::<_, LegacyBlock>(reader)
+                        .map_err(|e| anyhow!("Invalid record at offset {}: {}", frame.offset, e))?
+                        .block_level
+                }
+            };
+            if record_level >= level {
+                self.cursor = frame.offset;
+                return Ok(());
+            }
+            self.cursor = frame.end();
+        }
+        Ok(())
+    }
+
+    /// Decodes the next block selected by the filter
+    fn read_block(&mut self) -> Result<Option<(Block, Vec<ContextActionMessage>)>> {
+        if let Some(level) = self.seek_level.take() {
+            self.seek(level)?;
+        }
+        while let Some(record) = self.next_record()? {
+            let offset = record.offset;
+            let mut reader = snap::read::FrameDecoder::new(record.data.reader());
+            if !self.header.has_levels() {
+                let (block, actions) = bincode::deserialize_from::<_, (LegacyBlock, Vec<ContextActionMessage>)>(reader)
+                    .map_err(|e| anyhow!("Invalid record at offset {}: {}", offset, e))?;
+                let block = Block::from(block);
+                if !self.filter.matches_block(&block) {
+                    if self.filter.is_past(&block) {
+                        return Ok(None);
+                    }
+                    continue;
+                }
+                let actions = actions.into_iter().filter(|msg| self.filter.matches(msg)).collect();
+                return Ok(Some((block, actions)));
+            }
+
+            let block = bincode::deserialize_from::<_, Block>(&mut reader)
+                .map_err(|e| anyhow!("Invalid record at offset {}: {}", offset, e))?;
+            if !self.filter.matches_block(&block) {
+                if self.filter.is_past(&block) {
+                    return Ok(None);
+                }
+                continue;
+            }
+
+            let mut records = vec![];
+            reader.read_to_end(&mut records)?;
+            let records = bincode::deserialize::<Vec<RawActionRecord>>(&records)
+                .map_err(|e| anyhow!("Invalid record at offset {}: {}", offset, e))?;
+            let filter = &self.filter;
+            let actions = records.iter()
+                .filter(|record| filter.matches_record(record.record, record.perform, record.kind()))
+                .map(RawActionRecord::decode)
+                .filter(|msg| msg.as_ref().map(|msg| filter.matches_action(&msg.action)).unwrap_or(true))
+                .collect::<Result<Vec<_>>>()?;
+            return Ok(Some((block, actions)));
+        }
+        Ok(None)
     }
 }
 
+/// Position and summary of a block record in an actions file
+#[derive(Debug, Clone)]
+pub struct BlockEntry {
+    pub block: Block,
+    /// Offset of the record from the start of the file
+    pub offset: u64,
+    pub actions_count: u32,
+}
+
 impl Iterator for ActionsFileReader {
-    type Item = (Block, Vec<ContextActionMessage>);
+    type Item = Result<(Block, Vec<ContextActionMessage>)>;
 
     /// Return a tuple of a block and list action in the block
+    ///
+    /// A record that cannot be read or decoded is returned as an error, which ends the iteration.
     fn next(&mut self) -> Option<Self::Item> {
-        self.cursor = match self.reader.seek(SeekFrom::Start(self.cursor)) {
-            Ok(c) => {
-                c
-            }
-            Err(_) => {
-                return None;
-            }
-        };
-        let mut h = [0_u8; 4];
-        self.reader.read_exact(&mut h);
-        let content_len = u32::from_be_bytes(h);
-        if content_len <= 0 {
+        if self.failed {
             return None;
         }
-        let mut b = BytesMut::with_capacity(content_len as usize);
-        unsafe { b.set_len(content_len as usize) }
-        self.reader.read_exact(&mut b);
-
-        let mut reader = snap::read::FrameDecoder::new(b.reader());
-
-        let item = match bincode::deserialize_from::<_, (Block, Vec<ContextActionMessage>)>(reader) {
-            Ok(item) => {
-                item
-            }
-            Err(_) => {
-                return None;
+        match self.read_block() {
+            Ok(block) => block.map(Ok),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
             }
-        };
-        self.cursor += h.len() as u64 + content_len as u64;
-        Some(item)
+        }
     }
 }
 
@@ -185,26 +486,67 @@ impl Iterator for ActionsFileReader {
 pub struct ActionsFileWriter {
     header: ActionsFileHeader,
     file: File,
+    recorded_only: bool,
 }
 
 
 impl ActionsFileWriter {
+    /// Opens or creates an actions file in the current format version
+    ///
+    /// Files in another format version with blocks in them are not appended to, legacy
+    /// files are converted with `bintool migrate`.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let mut file = OpenOptions::new().write(true).create(true).read(true).open(path)?;
-        let mut reader = BufReader::new(file.try_clone()?);
-        reader.seek(SeekFrom::Start(0));
-        let mut h = [0_u8; HEADER_LEN];
-        reader.read_exact(&mut h);
-        let header = ActionsFileHeader::from(h);
+        let path = path.as_ref();
+        let mut file = OpenOptions::new().write(true).create(true).truncate(false).read(true).open(path)?;
+        let mut header = ActionsFileHeader::read_from(&mut file)?;
+        if header.version != FORMAT_VERSION {
+            if header.block_count > 0 {
+                return Err(anyhow!("{} is in actions file format version {}, convert it to version {} with `bintool migrate`", path.display(), header.version, FORMAT_VERSION));
+            }
+            file.set_len(0)?;
+            header = ActionsFileHeader::new();
+        }
         Ok(ActionsFileWriter {
             file,
             header,
+            recorded_only: false,
         })
     }
+    /// Drops actions without the `record` flag from the blocks passed to `update`
+    pub fn with_recorded_only(mut self, recorded_only: bool) -> Self {
+        self.recorded_only = recorded_only;
+        self
+    }
 
     pub fn header(&self) -> ActionsFileHeader {
         self.header
     }
+
+    /// Reads the block index of the file, see [`ActionsFileReader::index`]
+    pub fn index(&self) -> Result<Vec<BlockEntry>> {
+        ActionsFileReader::from_file(self.file.try_clone()?)?.index()
+    }
+
+    /// Drops every block above `level` from the file and returns the updated header
+    pub fn rollback(&mut self, level: u32) -> Result<ActionsFileHeader> {
+        let index = self.index()?;
+        let keep = match index.iter().position(|entry| entry.block.block_level > level) {
+            Some(keep) => keep,
+            None => return Ok(self.header),
+        };
+        let mut header = ActionsFileHeader::new();
+        for entry in &index[..keep] {
+            header.block_height = entry.block.block_level;
+            header.current_block_hash = entry.block.block_hash;
+            header.actions_count += entry.actions_count;
+            header.block_count += 1;
+        }
+        self.file.set_len(index[keep].offset)?;
+        self.header = header;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header.to_vec())?;
+        Ok(header)
+    }
 }
 
 
@@ -214,71 +556,287 @@ unsafe impl Sync for ActionsFileWriter {}
 
 
 impl ActionsFileWriter {
-    pub fn update(&mut self, block: Block, actions: Vec<ContextActionMessage>) -> Result<u32> {
+    pub fn update(&mut self, block: Block, mut actions: Vec<ContextActionMessage>) -> Result<u32> {
+        if self.recorded_only {
+            actions.retain(|msg| msg.record);
+        }
         let block_level = block.block_level;
         let actions_count = actions.len() as u32;
         let block_hash = block.block_hash;
-        self._fetch_header();
+        self._fetch_header()?;
 
         // Check if currently saved block precedes the incoming block
         if block.predecessor != self.header.current_block_hash && self.header.block_count > 0 {
             return Err(anyhow!("Block out of sequence"));
         }
 
+        let records = actions.iter().map(ActionRecord::encode).collect::<Result<Vec<_>>>()?;
         let mut out = Vec::new();
-        let mut writer = snap::write::FrameEncoder::new(&mut out);
-        bincode::serialize_into(writer, &(block, actions))?;
+        let writer = snap::write::FrameEncoder::new(&mut out);
+        bincode::serialize_into(writer, &(block, records))?;
 
         // Writes the header if its not already set
-        if self.header.block_count <= 0 {
+        if self.header.block_count == 0 {
             let header_bytes = self.header.to_vec();
-            self.file.seek(SeekFrom::Start(0));
-            self.file.write(&header_bytes);
+            self.file.rewind()?;
+            self.file.write_all(&header_bytes)?;
         }
-        self._update(&out);
-        self._update_header(block_level, actions_count, block_hash);
-        Ok((block_level + 1))
+        self._update(block_level, &out)?;
+        self._update_header(block_level, actions_count, block_hash)?;
+        Ok(block_level + 1)
     }
 
-    fn _update_header(&mut self, block_level: u32, actions_count: u32, block_hash: [u8; BLOCK_HASH_HEADER_LEN]) {
+    fn _update_header(&mut self, block_level: u32, actions_count: u32, block_hash: BlockHash) -> Result<()> {
         self.header.block_height = block_level;
         self.header.actions_count += actions_count;
         self.header.block_count += 1;
         self.header.current_block_hash = block_hash;
 
         let header_bytes = self.header.to_vec();
-        self.file.seek(SeekFrom::Start(0));
-        self.file.write(&header_bytes);
+        self.file.rewind()?;
+        self.file.write_all(&header_bytes)?;
+        Ok(())
     }
 
-    fn _fetch_header(&mut self) {
-        self.file.seek(SeekFrom::Start(0));
-        let mut h = [0_u8; HEADER_LEN];
-        self.file.read_exact(&mut h);
-        self.header = ActionsFileHeader::from(h);
+    fn _fetch_header(&mut self) -> Result<()> {
+        self.header = ActionsFileHeader::read_from(&mut self.file)?;
+        Ok(())
     }
 
-    pub fn _update(&mut self, data: &[u8]) {
-        self.file.seek(SeekFrom::End(0));
+    /// Appends a block record, framed by its length and block level
+    pub fn _update(&mut self, block_level: u32, data: &[u8]) -> Result<()> {
+        self.file.seek(SeekFrom::End(0))?;
         let header = (data.len() as u32).to_be_bytes();
         let mut dt = vec![];
         dt.extend_from_slice(&header);
+        dt.extend_from_slice(&block_level.to_be_bytes());
         dt.extend_from_slice(data);
-        self.file.write(dt.as_slice());
+        self.file.write_all(dt.as_slice())?;
+        Ok(())
     }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use crate::ActionsFileReader;
+    use crate::{ActionsFileReader, ActionsFileWriter, Block};
+    use crate::file::{FORMAT_VERSION, HEADER_LEN};
+    use crate::hash::BlockHash;
+    use crate::channel::{ActionKind, ContextAction, ContextActionMessage, FlagFilter};
+    use crate::ActionFilter;
+    use anyhow::Result;
+    use serde::Serialize;
+    use std::path::Path;
+
+    fn checkout(level: u8) -> ContextActionMessage {
+        ContextActionMessage {
+            action: ContextAction::Checkout { context_hash: vec![level; 32], start_time: 1.0, end_time: 2.0 },
+            record: true,
+            perform: true,
+        }
+    }
+
+    /// Writes blocks 1 to `levels` with one checkout each the way format version 0 did
+    fn write_legacy(path: &Path, levels: u8) {
+        #[derive(Serialize)]
+        struct LegacyBlock {
+            block_level: u32,
+            block_hash_hex: String,
+            block_hash: [u8; 32],
+            predecessor: [u8; 32],
+        }
+        let mut file = vec![];
+        file.extend_from_slice(&u32::from(levels).to_be_bytes());
+        file.extend_from_slice(&u32::from(levels).to_be_bytes());
+        file.extend_from_slice(&u32::from(levels).to_be_bytes());
+        file.extend_from_slice(&[levels; 32]);
+        for level in 1..=levels {
+            let block = LegacyBlock {
+                block_level: u32::from(level),
+                block_hash_hex: [level; 32].iter().map(|byte| format!("{:02x}", byte)).collect(),
+                block_hash: [level; 32],
+                predecessor: [level - 1; 32],
+            };
+            let mut record = vec![];
+            bincode::serialize_into(snap::write::FrameEncoder::new(&mut record), &(block, vec![checkout(level)])).unwrap();
+            file.extend_from_slice(&(record.len() as u32).to_be_bytes());
+            file.extend_from_slice(&record);
+        }
+        std::fs::write(path, file).unwrap();
+    }
 
     #[test]
     fn test_read() {
-        let reader = ActionsFileReader::new("/Users/mambisiz/CLionProjects/actions_tool/actions.bin").unwrap();
-        for (b,_msgs) in reader {
-            println!("{}", b.block_hash_hex);
-            break
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("actions.bin");
+        let mut writer = ActionsFileWriter::new(&path).unwrap();
+        assert_eq!(writer.update(Block::new(1, vec![1; 32], vec![0; 32]), vec![checkout(1)]).unwrap(), 2);
+        assert_eq!(writer.update(Block::new(2, vec![2; 32], vec![1; 32]), vec![]).unwrap(), 3);
+
+        let (block, msgs) = ActionsFileReader::new(&path).unwrap().next().unwrap().unwrap();
+        assert_eq!(block.block_hash, BlockHash::new([1; 32]));
+        assert_eq!(msgs, vec![checkout(1)]);
+        assert_eq!(ActionsFileReader::new(&path).unwrap().header().block_count, 2);
+    }
+
+    #[test]
+    fn test_unknown_action_is_preserved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("actions.bin");
+        let mut raw = 42_u32.to_le_bytes().to_vec();
+        raw.extend_from_slice(&[1, 2, 3]);
+        let actions = vec![
+            ContextActionMessage {
+                action: ContextAction::Unknown { tag: 42, raw: raw.clone() },
+                record: true,
+                perform: false,
+            },
+            ContextActionMessage {
+                action: ContextAction::Checkout { context_hash: vec![7; 32], start_time: 1.0, end_time: 2.0 },
+                record: true,
+                perform: true,
+            },
+        ];
+        let mut writer = ActionsFileWriter::new(&path).unwrap();
+        writer.update(Block::new(1, vec![1; 32], vec![0; 32]), actions).unwrap();
+        writer.update(Block::new(2, vec![2; 32], vec![1; 32]), vec![]).unwrap();
+
+        let blocks = ActionsFileReader::new(&path).unwrap().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(blocks.len(), 2);
+        let (_, msgs) = &blocks[0];
+        match &msgs[0].action {
+            ContextAction::Unknown { tag, raw: decoded } => {
+                assert_eq!(*tag, 42);
+                assert_eq!(decoded, &raw);
+            }
+            other => panic!("unexpected action {:?}", other),
         }
+        assert!(matches!(msgs[1].action, ContextAction::Checkout { .. }));
+    }
+
+    #[test]
+    fn test_record_and_perform_flags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("actions.bin");
+        let msg = |record: bool, perform: bool| ContextActionMessage {
+            action: ContextAction::Checkout { context_hash: vec![7; 32], start_time: 1.0, end_time: 2.0 },
+            record,
+            perform,
+        };
+        let actions = vec![msg(true, true), msg(true, false), msg(false, true), msg(false, false)];
+        let mut writer = ActionsFileWriter::new(&path).unwrap();
+        writer.update(Block::new(1, vec![1; 32], vec![0; 32]), actions.clone()).unwrap();
+        let mut writer = writer.with_recorded_only(true);
+        writer.update(Block::new(2, vec![2; 32], vec![1; 32]), actions).unwrap();
+        assert_eq!(writer.header().actions_count, 6);
+
+        let count = |flags: FlagFilter| ActionsFileReader::new(&path).unwrap()
+            .with_flags(flags)
+            .map(|item| item.unwrap().1.len())
+            .collect::<Vec<_>>();
+        assert_eq!(count(FlagFilter::ANY), vec![4, 2]);
+        assert_eq!(count(FlagFilter::recorded()), vec![2, 2]);
+        assert_eq!(count(FlagFilter::performed()), vec![2, 1]);
+        assert_eq!(count(FlagFilter { record: Some(false), perform: Some(false) }), vec![1, 0]);
+    }
+
+    #[test]
+    fn test_filter_is_applied_while_reading() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("actions.bin");
+        let mut writer = ActionsFileWriter::new(&path).unwrap();
+        for level in 1..=5_u8 {
+            let actions = vec![
+                ContextActionMessage {
+                    action: ContextAction::Checkout { context_hash: vec![level; 32], start_time: 1.0, end_time: 2.0 },
+                    record: true,
+                    perform: true,
+                },
+                ContextActionMessage {
+                    action: ContextAction::Mem {
+                        context_hash: None,
+                        block_hash: None,
+                        operation_hash: None,
+                        tree_hash: vec![],
+                        start_time: 2.0,
+                        end_time: 3.0,
+                        key: vec!["data".to_string(), format!("k{}", level)],
+                        value: true,
+                    },
+                    record: true,
+                    perform: level % 2 == 0,
+                },
+            ];
+            writer.update(Block::new(u32::from(level), vec![level; 32], vec![level - 1; 32]), actions).unwrap();
+        }
+
+        let read = |filter: ActionFilter| ActionsFileReader::new(&path).unwrap()
+            .with_filter(filter)
+            .map(|item| item.map(|(block, msgs)| (block.block_level, msgs.len())).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(read(ActionFilter::new()), vec![(1, 2), (2, 2), (3, 2), (4, 2), (5, 2)]);
+        assert_eq!(read(ActionFilter::new().levels(2..=3)), vec![(2, 2), (3, 2)]);
+        assert_eq!(read(ActionFilter::new().from_level(9)), vec![]);
+        assert_eq!(read(ActionFilter::new().block_hash(Block::new(4, vec![4; 32], vec![]).block_hash)), vec![(4, 2)]);
+        assert_eq!(read(ActionFilter::new().to_level(2).kind(ActionKind::Mem)), vec![(1, 1), (2, 1)]);
+        assert_eq!(read(ActionFilter::new().from_level(4).key_glob("data/k?")), vec![(4, 1), (5, 1)]);
+        assert_eq!(read(ActionFilter::new().levels(1..=2).flags(FlagFilter::performed()).key_prefix("data")), vec![(1, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn test_legacy_file_is_read_but_not_appended_to() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("actions.bin");
+        write_legacy(&path, 3);
+
+        let reader = ActionsFileReader::new(&path).unwrap();
+        assert_eq!(reader.header().version, 0);
+        assert_eq!(reader.header().block_count, 3);
+        let blocks = reader.collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(blocks.iter().map(|(block, _)| block.block_level).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(blocks[0].0.block_hash, BlockHash::new([1; 32]));
+        assert_eq!(blocks[1].0.predecessor, blocks[0].0.block_hash);
+        assert_eq!(blocks[2].1, vec![checkout(3)]);
+
+        let levels = ActionsFileReader::new(&path).unwrap()
+            .with_filter(ActionFilter::new().from_level(2).kind(ActionKind::Checkout))
+            .map(|item| item.map(|(block, msgs)| (block.block_level, msgs.len())).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(levels, vec![(2, 1), (3, 1)]);
+        assert!(ActionsFileWriter::new(&path).is_err());
+    }
+
+    #[test]
+    fn test_unreadable_files_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("actions.bin");
+        let mut writer = ActionsFileWriter::new(&path).unwrap();
+        writer.update(Block::new(1, vec![1; 32], vec![0; 32]), vec![checkout(1)]).unwrap();
+        writer.update(Block::new(2, vec![2; 32], vec![1; 32]), vec![checkout(2)]).unwrap();
+        assert_eq!(ActionsFileReader::new(&path).unwrap().header().version, FORMAT_VERSION);
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 3]).unwrap();
+        let mut reader = ActionsFileReader::new(&path).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+
+        // Seeking reads only the frames of the records it skips
+        let mut corrupt = bytes.clone();
+        corrupt[HEADER_LEN + 8..HEADER_LEN + 16].copy_from_slice(&[0xff; 8]);
+        std::fs::write(&path, &corrupt).unwrap();
+        assert!(ActionsFileReader::new(&path).unwrap().next().unwrap().is_err());
+        let levels = ActionsFileReader::new(&path).unwrap()
+            .with_filter(ActionFilter::new().from_level(2))
+            .map(|item| item.unwrap().0.block_level)
+            .collect::<Vec<_>>();
+        assert_eq!(levels, vec![2]);
+
+        let mut future = bytes;
+        future[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_be_bytes());
+        std::fs::write(&path, &future).unwrap();
+        assert!(ActionsFileReader::new(&path).is_err());
+        assert!(ActionsFileWriter::new(&path).is_err());
     }
 }
@@ -0,0 +1,227 @@
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+
+use crate::channel::{ActionKind, ContextAction, ContextActionMessage, FlagFilter};
+use crate::hash::{BlockHash, OperationHash};
+use crate::Block;
+
+/// Key paths matched by an `ActionFilter`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyPattern {
+    /// Keys starting with these segments
+    Prefix(Vec<String>),
+    /// Key paths joined with `/` matching a glob, `*` matches any run of characters and `?` one
+    Glob(String),
+}
+
+impl KeyPattern {
+    pub fn matches(&self, key: &[String]) -> bool {
+        match self {
+            KeyPattern::Prefix(prefix) => key.len() >= prefix.len() && key[..prefix.len()] == prefix[..],
+            KeyPattern::Glob(glob) => glob_match(glob.as_bytes(), key.join("/").as_bytes()),
+        }
+    }
+}
+
+/// Selects blocks and actions read from an actions file
+///
+/// Built up from `ActionFilter::new()`, which matches everything; every criterion that is set
+/// has to match. Level and block hash criteria select blocks, the others the actions within
+/// them. Passed to `ActionsFileReader::with_filter`, blocks before the level range are skipped
+/// by scanning the record frames, which carry the block level in the current format version so
+/// the records are not decompressed, and actions are only decoded if their flags and kind match.
+#[derive(Clone, Debug, Default)]
+pub struct ActionFilter {
+    pub(crate) from_level: Option<u32>,
+    pub(crate) to_level: Option<u32>,
+    block_hashes: Option<HashSet<BlockHash>>,
+    kinds: Option<HashSet<ActionKind>>,
+    key: Option<KeyPattern>,
+    operation_hash: Option<OperationHash>,
+    pub(crate) flags: FlagFilter,
+}
+
+impl ActionFilter {
+    pub fn new() -> Self {
+        ActionFilter::default()
+    }
+
+    pub fn levels(self, levels: RangeInclusive<u32>) -> Self {
+        self.from_level(*levels.start()).to_level(*levels.end())
+    }
+
+    pub fn from_level(mut self, level: u32) -> Self {
+        self.from_level = Some(level);
+        self
+    }
+
+    /// Like `from_level`, but keeps a later first level already set
+    pub fn from_level_at_least(self, level: u32) -> Self {
+        match self.from_level {
+            Some(from_level) if from_level >= level => self,
+            _ => self.from_level(level),
+        }
+    }
+
+    pub fn to_level(mut self, level: u32) -> Self {
+        self.to_level = Some(level);
+        self
+    }
+
+    /// Adds a block to select, can be called repeatedly
+    pub fn block_hash(mut self, hash: BlockHash) -> Self {
+        self.block_hashes.get_or_insert_with(HashSet::new).insert(hash);
+        self
+    }
+
+    pub fn block_hashes<I: IntoIterator<Item = BlockHash>>(self, hashes: I) -> Self {
+        hashes.into_iter().fold(self, ActionFilter::block_hash)
+    }
+
+    /// Adds an action kind to select, can be called repeatedly
+    pub fn kind(mut self, kind: ActionKind) -> Self {
+        self.kinds.get_or_insert_with(HashSet::new).insert(kind);
+        self
+    }
+
+    pub fn kinds<I: IntoIterator<Item = ActionKind>>(self, kinds: I) -> Self {
+        kinds.into_iter().fold(self, ActionFilter::kind)
+    }
+
+    /// Selects actions whose key starts with the segments of `prefix`, e.g. `data/contracts/index`
+    pub fn key_prefix(mut self, prefix: &str) -> Self {
+        let prefix = prefix.split('/').filter(|segment| !segment.is_empty()).map(str::to_string).collect();
+        self.key = Some(KeyPattern::Prefix(prefix));
+        self
+    }
+
+    /// Selects actions whose key path matches `glob`, e.g. `data/contracts/index/*/balance`
+    pub fn key_glob(mut self, glob: &str) -> Self {
+        self.key = Some(KeyPattern::Glob(glob.to_string()));
+        self
+    }
+
+    pub fn operation_hash(mut self, hash: OperationHash) -> Self {
+        self.operation_hash = Some(hash);
+        self
+    }
+
+    pub fn flags(mut self, flags: FlagFilter) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn matches_block(&self, block: &Block) -> bool {
+        self.from_level.map(|level| block.block_level >= level).unwrap_or(true)
+            && self.to_level.map(|level| block.block_level <= level).unwrap_or(true)
+            && self.block_hashes.as_ref().map(|hashes| hashes.contains(&block.block_hash)).unwrap_or(true)
+    }
+
+    /// Whether the block comes after the level range, and with it every following block
+    pub(crate) fn is_past(&self, block: &Block) -> bool {
+        self.to_level.map(|level| block.block_level > level).unwrap_or(false)
+    }
+
+    /// Checks what is known about an action before it is decoded
+    pub(crate) fn matches_record(&self, record: bool, perform: bool, kind: ActionKind) -> bool {
+        self.flags.matches_flags(record, perform) && self.kinds.as_ref().map(|kinds| kinds.contains(&kind)).unwrap_or(true)
+    }
+
+    /// Checks the criteria that need the decoded action
+    pub(crate) fn matches_action(&self, action: &ContextAction) -> bool {
+        let key = match &self.key {
+            Some(pattern) => action.key().map(|key| pattern.matches(key)).unwrap_or(false),
+            None => true,
+        };
+        let operation = match &self.operation_hash {
+            Some(hash) => action.operation_hash().map(|operation| operation[..] == hash.as_ref()[..]).unwrap_or(false),
+            None => true,
+        };
+        key && operation
+    }
+
+    /// Whether the message matches the action criteria, for messages that do not come from a file
+    pub fn matches(&self, msg: &ContextActionMessage) -> bool {
+        self.matches_record(msg.record, msg.perform, msg.action.kind()) && self.matches_action(&msg.action)
+    }
+}
+
+/// Matches `text` against `glob`, backtracking to the last `*`
+fn glob_match(glob: &[u8], text: &[u8]) -> bool {
+    let (mut g, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match glob.get(g) {
+            Some(b'*') => {
+                star = Some((g, t));
+                g += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                g += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_g, star_t)) => {
+                    g = star_g + 1;
+                    t = star_t + 1;
+                    star = Some((star_g, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(path: &str) -> Vec<String> {
+        path.split('/').map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_key_patterns() {
+        let prefix = KeyPattern::Prefix(key("data/contracts"));
+        assert!(prefix.matches(&key("data/contracts/index/a")));
+        assert!(!prefix.matches(&key("data/contracts_x")));
+        assert!(!prefix.matches(&key("data")));
+
+        let glob = KeyPattern::Glob("data/contracts/index/*/balance".to_string());
+        assert!(glob.matches(&key("data/contracts/index/ab/cd/balance")));
+        assert!(!glob.matches(&key("data/contracts/index/ab/manager")));
+        assert!(KeyPattern::Glob("data/v?tes".to_string()).matches(&key("data/votes")));
+        assert!(KeyPattern::Glob("*".to_string()).matches(&key("")));
+    }
+
+    #[test]
+    fn test_block_and_action_criteria() {
+        let filter = ActionFilter::new().levels(2..=3).block_hashes(vec![BlockHash::new([2; 32]), BlockHash::new([4; 32])]);
+        assert!(filter.matches_block(&Block::new(2, vec![2; 32], vec![1; 32])));
+        assert!(!filter.matches_block(&Block::new(3, vec![3; 32], vec![2; 32])));
+        assert!(!filter.matches_block(&Block::new(4, vec![4; 32], vec![3; 32])));
+        assert!(filter.is_past(&Block::new(4, vec![4; 32], vec![3; 32])));
+
+        let filter = ActionFilter::new().kind(ActionKind::Get).key_prefix("data/").operation_hash(OperationHash::new([5; 32]));
+        let get = |operation_hash: Option<Vec<u8>>| ContextActionMessage {
+            action: ContextAction::Get {
+                context_hash: None,
+                block_hash: None,
+                operation_hash,
+                tree_hash: vec![],
+                start_time: 0.0,
+                end_time: 0.0,
+                key: key("data/votes"),
+                value: vec![],
+                value_as_json: None,
+            },
+            record: true,
+            perform: true,
+        };
+        assert!(filter.matches(&get(Some(vec![5; 32]))));
+        assert!(!filter.matches(&get(Some(vec![6; 32]))));
+        assert!(!filter.matches(&get(None)));
+        assert!(!filter.clone().flags(FlagFilter { record: Some(false), perform: None }).matches(&get(Some(vec![5; 32]))));
+    }
+}
@@ -0,0 +1,154 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Length of every Tezos hash handled by this crate.
+pub const HASH_LEN: usize = 32;
+
+macro_rules! tezos_hash {
+    ($(#[$meta:meta])* $name:ident, $prefix:expr) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name([u8; HASH_LEN]);
+
+        impl $name {
+            /// Base58check version prefix of the hash.
+            pub const PREFIX: [u8; 2] = $prefix;
+
+            pub fn new(bytes: [u8; HASH_LEN]) -> Self {
+                $name(bytes)
+            }
+
+            pub fn as_bytes(&self) -> &[u8; HASH_LEN] {
+                &self.0
+            }
+
+            pub fn to_b58check(&self) -> String {
+                let mut data = Vec::with_capacity(Self::PREFIX.len() + HASH_LEN);
+                data.extend_from_slice(&Self::PREFIX);
+                data.extend_from_slice(&self.0);
+                bs58::encode(data).with_check().into_string()
+            }
+
+            pub fn from_b58check(s: &str) -> Result<Self> {
+                let data = bs58::decode(s)
+                    .with_check(None)
+                    .into_vec()
+                    .map_err(|e| anyhow!("Invalid {} {}: {}", stringify!($name), s, e))?;
+                if data.len() != Self::PREFIX.len() + HASH_LEN || data[..Self::PREFIX.len()] != Self::PREFIX {
+                    return Err(anyhow!("Invalid {} {}: wrong prefix or length", stringify!($name), s));
+                }
+                Self::try_from(&data[Self::PREFIX.len()..])
+            }
+        }
+
+        impl From<[u8; HASH_LEN]> for $name {
+            fn from(bytes: [u8; HASH_LEN]) -> Self {
+                $name(bytes)
+            }
+        }
+
+        impl TryFrom<&[u8]> for $name {
+            type Error = anyhow::Error;
+
+            fn try_from(bytes: &[u8]) -> Result<Self> {
+                let bytes = <[u8; HASH_LEN]>::try_from(bytes)
+                    .map_err(|_| anyhow!("Invalid {} length: {}", stringify!($name), bytes.len()))?;
+                Ok($name(bytes))
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.to_b58check())
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({})", stringify!($name), self.to_b58check())
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = anyhow::Error;
+
+            fn from_str(s: &str) -> Result<Self> {
+                Self::from_b58check(s)
+            }
+        }
+
+        /// Human readable formats get the b58check string, binary ones the raw bytes.
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+                if serializer.is_human_readable() {
+                    serializer.serialize_str(&self.to_b58check())
+                } else {
+                    self.0.serialize(serializer)
+                }
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+                if deserializer.is_human_readable() {
+                    let s = String::deserialize(deserializer)?;
+                    Self::from_b58check(&s).map_err(D::Error::custom)
+                } else {
+                    <[u8; HASH_LEN]>::deserialize(deserializer).map($name)
+                }
+            }
+        }
+    };
+}
+
+tezos_hash!(
+    /// Block hash, `B...` in b58check form.
+    BlockHash,
+    [1, 52]
+);
+tezos_hash!(
+    /// Context hash, `Co...` in b58check form.
+    ContextHash,
+    [79, 199]
+);
+tezos_hash!(
+    /// Operation hash, `o...` in b58check form.
+    OperationHash,
+    [5, 116]
+);
+tezos_hash!(
+    /// Protocol hash, `P...` in b58check form.
+    ProtocolHash,
+    [2, 170]
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_b58check_round_trip() {
+        let genesis = "BLockGenesisGenesisGenesisGenesisGenesisf79b5d1CoW2";
+        let hash: BlockHash = genesis.parse().unwrap();
+        assert_eq!(hash.to_string(), genesis);
+        assert!(genesis.parse::<ContextHash>().is_err());
+
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(json, format!("\"{}\"", genesis));
+        assert_eq!(serde_json::from_str::<BlockHash>(&json).unwrap(), hash);
+        let bin = bincode::serialize(&hash).unwrap();
+        assert_eq!(bin.len(), HASH_LEN);
+        assert_eq!(bincode::deserialize::<BlockHash>(&bin).unwrap(), hash);
+    }
+}
@@ -0,0 +1,221 @@
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::channel::{ActionChannel, ContextAction, ContextActionMessage};
+
+/// Largest accepted frame, guards against allocating for a corrupted length
+const MAX_FRAME_LEN: u32 = 256 * 1024 * 1024;
+
+/// How `IpcSender` batches messages and reconnects
+#[derive(Debug, Clone, Copy)]
+pub struct IpcConfig {
+    /// Most messages written in one frame
+    pub batch_size: usize,
+    /// Wait before the first reconnect, doubled on every further attempt up to `max_backoff`
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Reconnect attempts before a batch is given up, `None` to retry forever
+    pub max_retries: Option<u32>,
+}
+
+impl Default for IpcConfig {
+    fn default() -> Self {
+        IpcConfig {
+            batch_size: 1024,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            max_retries: None,
+        }
+    }
+}
+
+/// Sending side of the Unix socket transport, run in the process that produces the actions
+///
+/// The producer keeps using an `ActionChannel`, e.g. through `context_send`, and `run`
+/// forwards what it queues to an `IpcReceiver` in another process. Each frame is a
+/// big-endian `u32` length followed by a bincode encoded batch of messages.
+pub struct IpcSender {
+    path: PathBuf,
+    config: IpcConfig,
+    stream: Option<BufWriter<UnixStream>>,
+}
+
+impl IpcSender {
+    /// Sends to the socket at `path`, connecting on the first batch
+    pub fn new<P: AsRef<Path>>(path: P, config: IpcConfig) -> Self {
+        IpcSender {
+            path: path.as_ref().to_path_buf(),
+            config,
+            stream: None,
+        }
+    }
+
+    /// Writes one frame, reconnecting with backoff until it is written or the retries run out
+    pub fn send_batch(&mut self, batch: &[ContextActionMessage]) -> Result<()> {
+        let payload = bincode::serialize(batch)?;
+        let mut attempt = 0;
+        loop {
+            match self.write_frame(&payload) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    self.stream = None;
+                    if self.config.max_retries.map(|max| attempt >= max).unwrap_or(false) {
+                        return Err(e.context(format!("giving up after {} reconnects", attempt)));
+                    }
+                }
+            }
+            let factor = 2_u32.saturating_pow(attempt);
+            let backoff = self.config.initial_backoff.checked_mul(factor).unwrap_or(self.config.max_backoff);
+            std::thread::sleep(backoff.min(self.config.max_backoff));
+            attempt += 1;
+        }
+    }
+
+    /// Forwards the messages of `channel` until `Shutdown`, which is forwarded as well
+    ///
+    /// Closes `channel` if forwarding fails.
+    pub fn run(mut self, channel: &ActionChannel) -> Result<()> {
+        while let Ok(batch) = channel.receive_many(self.config.batch_size) {
+            if let Err(e) = self.send_batch(&batch) {
+                channel.close();
+                return Err(e);
+            }
+            if let Some(ContextActionMessage { action: ContextAction::Shutdown, .. }) = batch.last() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self, payload: &[u8]) -> Result<()> {
+        if self.stream.is_none() {
+            let stream = UnixStream::connect(&self.path)
+                .with_context(|| format!("Cannot connect to {}", self.path.display()))?;
+            self.stream = Some(BufWriter::new(stream));
+        }
+        let stream = self.stream.as_mut().unwrap();
+        stream.write_all(&(payload.len() as u32).to_be_bytes())?;
+        stream.write_all(payload)?;
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+/// Receiving side of the Unix socket transport, run in the recorder process
+///
+/// Messages are queued into an `ActionChannel`, so consumers use the same API as in process.
+pub struct IpcReceiver {
+    listener: UnixListener,
+}
+
+impl IpcReceiver {
+    /// Listens at `path`, replacing a socket left over by a previous run
+    pub fn bind<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(anyhow!("Cannot listen on {}, it exists and is not a socket", path.display()));
+            }
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path).with_context(|| format!("Cannot listen on {}", path.display()))?;
+        Ok(IpcReceiver { listener })
+    }
+
+    /// Queues received messages into `channel` until a `Shutdown` arrives
+    ///
+    /// A sender that disconnects may reconnect, connections are served one after another. A
+    /// connection that sends a malformed frame is dropped. On `Shutdown` the channel is shut down, which waits until its consumer drained it.
+    pub fn run(&self, channel: &ActionChannel) -> Result<()> {
+        for stream in self.listener.incoming() {
+            let mut reader = BufReader::new(stream?);
+            while let Ok(Some(batch)) = read_frame(&mut reader) {
+                for msg in batch {
+                    if let ContextAction::Shutdown = msg.action {
+                        channel.shutdown();
+                        return Ok(());
+                    }
+                    let _ = channel.send(msg);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the next frame, `None` once the sender disconnected
+///
+/// A frame cut off by a disconnect is discarded, the sender writes it again after reconnecting.
+fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Vec<ContextActionMessage>>> {
+    let mut len = [0_u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => (),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof || e.kind() == ErrorKind::ConnectionReset => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_be_bytes(len);
+    if len > MAX_FRAME_LEN {
+        return Err(anyhow!("Frame of {} bytes exceeds the limit of {}", len, MAX_FRAME_LEN));
+    }
+    let mut payload = vec![0_u8; len as usize];
+    match reader.read_exact(&mut payload) {
+        Ok(()) => Ok(Some(bincode::deserialize(&payload)?)),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof || e.kind() == ErrorKind::ConnectionReset => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::channel::msg as checkout;
+
+    /// Performed only for even hashes, so the flags are seen to cross the socket
+    fn msg(hash: u8) -> ContextActionMessage {
+        ContextActionMessage { perform: hash.is_multiple_of(2), ..checkout(hash) }
+    }
+
+    #[test]
+    fn test_forward_over_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("actions.sock");
+        let config = IpcConfig { batch_size: 2, initial_backoff: Duration::from_millis(5), ..IpcConfig::default() };
+
+        // The sender retries until the receiver is listening
+        let mut early = IpcSender::new(&path, config);
+        let connecting = std::thread::spawn(move || early.send_batch(&[msg(1)]));
+        std::thread::sleep(Duration::from_millis(20));
+
+        let received = ActionChannel::new(16);
+        let receiver = IpcReceiver::bind(&path).unwrap();
+        let listening = {
+            let received = received.clone();
+            std::thread::spawn(move || receiver.run(&received))
+        };
+        connecting.join().unwrap().unwrap();
+        UnixStream::connect(&path).unwrap().write_all(&u32::MAX.to_be_bytes()).unwrap();
+
+        let produced = ActionChannel::new(16);
+        (2..=4).for_each(|hash| produced.send(msg(hash)).unwrap());
+        produced.send(ContextActionMessage::shutdown()).unwrap();
+        IpcSender::new(&path, config).run(&produced).unwrap();
+
+        let messages: Vec<_> = received.iter().collect();
+        assert_eq!(messages, (1..=4).map(msg).collect::<Vec<_>>());
+        listening.join().unwrap().unwrap();
+
+        let mut refused = IpcSender::new(dir.path().join("missing.sock"), IpcConfig { max_retries: Some(1), ..config });
+        assert!(refused.send_batch(&[msg(1)]).is_err());
+
+        let file = dir.path().join("actions.sock.bin");
+        std::fs::write(&file, b"keep").unwrap();
+        assert!(IpcReceiver::bind(&file).is_err());
+        assert_eq!(std::fs::read(&file).unwrap(), b"keep");
+    }
+}
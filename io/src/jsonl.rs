@@ -0,0 +1,166 @@
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::channel::{ContextAction, ContextActionMessage};
+use crate::hash::{BlockHash, ContextHash, OperationHash};
+use crate::Block;
+
+/// One line of a JSON Lines actions export
+///
+/// Every action of a block gets its own line, tagged with the block. A block without actions
+/// is kept as a single line with a `null` action, so that the export round-trips losslessly.
+/// Block, context and operation hashes inside the action are written in b58check form.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ActionLine {
+    pub block_level: u32,
+    pub block_hash: BlockHash,
+    pub predecessor: BlockHash,
+    pub record: bool,
+    pub perform: bool,
+    pub action: Option<Value>,
+}
+
+impl ActionLine {
+    pub fn new(block: &Block, msg: Option<&ContextActionMessage>) -> Result<Self> {
+        let action = match msg {
+            Some(msg) => {
+                let mut action = serde_json::to_value(&msg.action)?;
+                map_hash_fields(&mut action, &HashCodec::Encode);
+                Some(action)
+            }
+            None => None,
+        };
+        let (record, perform) = msg.map(|msg| (msg.record, msg.perform)).unwrap_or((false, false));
+        Ok(ActionLine {
+            block_level: block.block_level,
+            block_hash: block.block_hash,
+            predecessor: block.predecessor,
+            record,
+            perform,
+            action,
+        })
+    }
+
+    pub fn block(&self) -> Block {
+        Block {
+            block_level: self.block_level,
+            block_hash: self.block_hash,
+            predecessor: self.predecessor,
+        }
+    }
+
+    /// Returns the message on the line, `None` for the line of an empty block
+    pub fn message(&self) -> Result<Option<ContextActionMessage>> {
+        let mut action = match &self.action {
+            Some(action) => action.clone(),
+            None => return Ok(None),
+        };
+        map_hash_fields(&mut action, &HashCodec::Decode);
+        Ok(Some(ContextActionMessage {
+            action: serde_json::from_value::<ContextAction>(action)?,
+            record: self.record,
+            perform: self.perform,
+        }))
+    }
+}
+
+/// Converts all lines of a block, a single line with a `null` action if it has none
+pub fn block_lines(block: &Block, actions: &[ContextActionMessage]) -> Result<Vec<ActionLine>> {
+    if actions.is_empty() {
+        return Ok(vec![ActionLine::new(block, None)?]);
+    }
+    actions.iter().map(|msg| ActionLine::new(block, Some(msg))).collect()
+}
+
+enum HashCodec {
+    Encode,
+    Decode,
+}
+
+/// Rewrites the hash fields of a serialized `ContextAction` between byte arrays and b58check
+fn map_hash_fields(action: &mut Value, codec: &HashCodec) {
+    let fields = match action.as_object_mut().and_then(|variant| variant.values_mut().next()) {
+        Some(Value::Object(fields)) => fields,
+        _ => return,
+    };
+    for (name, value) in fields.iter_mut() {
+        match name.as_str() {
+            "block_hash" => codec.apply::<BlockHash>(value),
+            "operation_hash" => codec.apply::<OperationHash>(value),
+            "context_hash" | "parent_context_hash" | "new_context_hash" => codec.apply::<ContextHash>(value),
+            "parents" => {
+                if let Value::Array(parents) = value {
+                    parents.iter_mut().for_each(|parent| codec.apply::<ContextHash>(parent));
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl HashCodec {
+    /// Leaves values that are not valid hashes untouched, e.g. `null` or bytes of another length
+    fn apply<H>(&self, value: &mut Value)
+        where H: for<'a> TryFrom<&'a [u8]> + FromStr + Display + AsRef<[u8]> {
+        match self {
+            HashCodec::Encode => {
+                let bytes = match serde_json::from_value::<Vec<u8>>(value.clone()) {
+                    Ok(bytes) => bytes,
+                    Err(_) => return,
+                };
+                let hash = H::try_from(bytes.as_slice()).ok().map(|hash| hash.to_string());
+                if let Some(hash) = hash {
+                    *value = Value::String(hash);
+                }
+            }
+            HashCodec::Decode => {
+                if let Some(hash) = value.as_str().and_then(|s| s.parse::<H>().ok()) {
+                    *value = Value::from(hash.as_ref().to_vec());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_round_trip() {
+        let block = Block::new(7, vec![7; 32], vec![6; 32]);
+        let msg = ContextActionMessage {
+            action: ContextAction::Commit {
+                parent_context_hash: Some(vec![1; 32]),
+                block_hash: Some(vec![7; 32]),
+                new_context_hash: vec![2; 32],
+                tree_hash: vec![3; 32],
+                start_time: 1.0,
+                end_time: 2.0,
+                author: "Tezos".to_string(),
+                message: "".to_string(),
+                date: 1,
+                parents: vec![vec![1; 32]],
+            },
+            record: true,
+            perform: false,
+        };
+        let line = serde_json::to_string(&ActionLine::new(&block, Some(&msg)).unwrap()).unwrap();
+        let value: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["action"]["Commit"]["block_hash"], Value::from(block.block_hash.to_string()));
+        assert!(value["action"]["Commit"]["new_context_hash"].as_str().unwrap().starts_with("Co"));
+
+        let parsed: ActionLine = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed.block_hash, block.block_hash);
+        assert_eq!(parsed.message().unwrap(), Some(msg));
+
+        let empty = block_lines(&block, &[]).unwrap();
+        assert_eq!(empty.len(), 1);
+        assert_eq!(empty[0].message().unwrap(), None);
+    }
+}
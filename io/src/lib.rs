@@ -1,7 +1,18 @@
 mod file;
+pub mod broadcast;
 pub mod channel;
+pub mod filter;
+pub mod hash;
+#[cfg(unix)]
+pub mod ipc;
+pub mod jsonl;
+pub mod recorder;
+pub mod replay;
 
 
 pub use crate::file::ActionsFileReader;
 pub use crate::file::ActionsFileWriter;
 pub use crate::file::Block;
+pub use crate::file::BlockEntry;
+pub use crate::file::ActionsFileHeader;
+pub use crate::filter::ActionFilter;
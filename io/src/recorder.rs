@@ -0,0 +1,193 @@
+use std::convert::TryFrom;
+
+use anyhow::{anyhow, Result};
+
+use crate::channel::{ActionChannel, ContextAction, ContextActionMessage};
+use crate::hash::BlockHash;
+use crate::{ActionsFileWriter, Block};
+
+/// Records the actions sent through the context channel into an actions file
+///
+/// Actions are buffered until a `Commit`, which closes the block: the buffered actions and the
+/// commit are written as the block named by the commit's `block_hash`, at the level following
+/// the last written one. Recording continues an existing file after its last block.
+///
+/// Actions without the `record` flag are not written; a commit without it still closes its block.
+pub struct Recorder {
+    writer: ActionsFileWriter,
+    level: u32,
+    predecessor: BlockHash,
+    pending: Vec<ContextActionMessage>,
+}
+
+impl Recorder {
+    /// Records into `writer`, an empty file starts at level 1
+    pub fn new(writer: ActionsFileWriter) -> Self {
+        let header = writer.header();
+        let level = if header.block_count > 0 { header.block_height + 1 } else { 1 };
+        Recorder {
+            writer,
+            level,
+            predecessor: header.current_block_hash,
+            pending: vec![],
+        }
+    }
+
+    /// Sets the level of the next committed block, for recordings that do not start at level 1
+    pub fn with_level(mut self, level: u32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Level the next committed block is written at
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    /// Actions received since the last commit
+    pub fn pending(&self) -> &[ContextActionMessage] {
+        &self.pending
+    }
+
+    /// Handles one message, returns `false` once it is a `Shutdown`
+    pub fn handle(&mut self, msg: ContextActionMessage) -> Result<bool> {
+        let block_hash = match &msg.action {
+            ContextAction::Shutdown => return Ok(false),
+            ContextAction::Commit { block_hash, .. } => block_hash.clone(),
+            _ => {
+                if msg.record {
+                    self.pending.push(msg);
+                }
+                return Ok(true);
+            }
+        };
+        let block_hash = block_hash.ok_or_else(|| anyhow!("Commit at level {} has no block hash", self.level))?;
+        let block = Block {
+            block_level: self.level,
+            block_hash: BlockHash::try_from(block_hash.as_slice())?,
+            predecessor: self.predecessor,
+        };
+        if msg.record {
+            self.pending.push(msg);
+        }
+        let actions = std::mem::take(&mut self.pending);
+        self.predecessor = block.block_hash;
+        self.level = self.writer.update(block, actions)?;
+        Ok(true)
+    }
+
+    /// Records messages received from `channel` until `Shutdown` and returns the writer
+    ///
+    /// Actions received after the last commit belong to no block and are dropped. If recording
+    /// fails, the channel is closed, so `shutdown` returns instead of waiting for the recorder.
+    pub fn run(mut self, channel: &ActionChannel) -> Result<ActionsFileWriter> {
+        for msg in channel.iter() {
+            self.handle(msg)?;
+        }
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::msg;
+    use crate::ActionsFileReader;
+
+    fn commit(block_hash: Option<Vec<u8>>) -> ContextActionMessage {
+        ContextActionMessage {
+            action: ContextAction::Commit {
+                parent_context_hash: None,
+                block_hash,
+                new_context_hash: vec![2; 32],
+                tree_hash: vec![],
+                start_time: 0.1,
+                end_time: 0.2,
+                author: "Tezos".to_string(),
+                message: "".to_string(),
+                date: 0,
+                parents: vec![],
+            },
+            record: true,
+            perform: true,
+        }
+    }
+
+    #[test]
+    fn test_commit_delimits_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("actions.bin");
+        let mut recorder = Recorder::new(ActionsFileWriter::new(&path).unwrap()).with_level(10);
+        for action in [msg(1), msg(1), commit(Some(vec![10; 32])), commit(Some(vec![11; 32])), msg(1)] {
+            assert!(recorder.handle(action).unwrap());
+        }
+        assert_eq!(recorder.level(), 12);
+        assert_eq!(recorder.pending().len(), 1);
+        assert!(!recorder.handle(ContextActionMessage::shutdown()).unwrap());
+        assert!(recorder.handle(commit(None)).is_err());
+
+        let blocks = ActionsFileReader::new(&path).unwrap().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].0.block_level, 10);
+        assert_eq!(blocks[0].1.len(), 3);
+        assert_eq!(blocks[1].0.predecessor, blocks[0].0.block_hash);
+        assert_eq!(blocks[1].1, vec![commit(Some(vec![11; 32]))]);
+
+        let recorder = Recorder::new(ActionsFileWriter::new(&path).unwrap());
+        assert_eq!(recorder.level(), 12);
+    }
+
+    #[test]
+    fn test_run_until_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("actions.bin");
+        let channel = ActionChannel::new(16);
+        let recorder = Recorder::new(ActionsFileWriter::new(&path).unwrap());
+        let handle = {
+            let channel = channel.clone();
+            std::thread::spawn(move || recorder.run(&channel))
+        };
+        channel.send(msg(1)).unwrap();
+        channel.send(commit(Some(vec![1; 32]))).unwrap();
+        channel.shutdown();
+
+        let writer = handle.join().unwrap().unwrap();
+        assert_eq!(writer.header().block_count, 1);
+        assert_eq!(writer.header().block_height, 1);
+    }
+
+    #[test]
+    fn test_shutdown_after_failed_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let channel = ActionChannel::new(1);
+        let recorder = Recorder::new(ActionsFileWriter::new(dir.path().join("actions.bin")).unwrap());
+        let handle = {
+            let channel = channel.clone();
+            std::thread::spawn(move || recorder.run(&channel))
+        };
+        channel.send(commit(None)).unwrap();
+        assert!(handle.join().unwrap().is_err());
+
+        assert!(channel.send(msg(1)).is_err());
+        channel.shutdown();
+        assert!(!channel.shutdown_timeout(std::time::Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_unrecorded_actions_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("actions.bin");
+        let mut recorder = Recorder::new(ActionsFileWriter::new(&path).unwrap());
+        let unrecorded = |mut msg: ContextActionMessage| {
+            msg.record = false;
+            msg
+        };
+        for action in [msg(1), unrecorded(msg(1)), unrecorded(commit(Some(vec![1; 32])))] {
+            recorder.handle(action).unwrap();
+        }
+        assert_eq!(recorder.level(), 2);
+
+        let blocks = ActionsFileReader::new(&path).unwrap().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(blocks[0].1, vec![msg(1)]);
+    }
+}
@@ -0,0 +1,124 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use crossbeam::channel::SendTimeoutError;
+
+use crate::channel::ActionChannel;
+use crate::ActionsFileReader;
+
+/// How fast `replay` sends the actions of a file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pacing {
+    /// Sends every action as soon as the channel takes it
+    Unpaced,
+    /// Sends every action at its recorded `start_time`, relative to the first action
+    Recorded,
+    /// Like `Recorded`, with time running `speed` times as fast, e.g. `2.0` replays in half the time
+    Scaled(f64),
+}
+
+impl Pacing {
+    fn speed(&self) -> Option<f64> {
+        match self {
+            Pacing::Unpaced => None,
+            Pacing::Recorded => Some(1.0),
+            Pacing::Scaled(speed) => Some(*speed),
+        }
+    }
+}
+
+/// Sends the actions of `reader` into `channel`, then shuts the channel down
+///
+/// Actions without recorded times are sent right after the preceding one. Returns the number
+/// of actions the channel accepted once the consumer has drained it. Fails once the consumer
+/// closes the channel, see `ActionChannel::close`.
+pub fn replay(reader: ActionsFileReader, channel: &ActionChannel, pacing: Pacing) -> Result<u64> {
+    let speed = pacing.speed();
+    if let Some(speed) = speed {
+        if !(speed.is_finite() && speed > 0.0) {
+            return Err(anyhow!("Invalid replay speed {}", speed));
+        }
+    }
+    let started = Instant::now();
+    let mut first_start = None;
+    let mut count = 0;
+    for item in reader {
+        let (_, actions) = item?;
+        for msg in actions {
+            if let (Some(speed), Some(start)) = (speed, msg.action.start_time()) {
+                let offset = (start - *first_start.get_or_insert(start)) / speed;
+                let due = started + Duration::from_secs_f64(offset.max(0.0));
+                let now = Instant::now();
+                if due > now {
+                    std::thread::sleep(due - now);
+                }
+            }
+            match channel.send(msg) {
+                Ok(()) => count += 1,
+                Err(SendTimeoutError::Timeout(_)) => (),
+                Err(SendTimeoutError::Disconnected(_)) => return Err(anyhow!("Channel disconnected after {} actions", count)),
+            }
+        }
+    }
+    channel.shutdown();
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::{msg, ContextAction, OverflowPolicy};
+    use crate::{ActionsFileWriter, Block};
+
+    #[test]
+    fn test_replay_pacing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("actions.bin");
+        let mut writer = ActionsFileWriter::new(&path).unwrap();
+        for level in 1..=3_u8 {
+            let mut checkout = msg(level);
+            if let ContextAction::Checkout { start_time, .. } = &mut checkout.action {
+                *start_time = 100.0 + f64::from(level) * 0.05;
+            }
+            writer.update(Block::new(u32::from(level), vec![level; 32], vec![level - 1; 32]), vec![checkout]).unwrap();
+        }
+
+        for (pacing, min, max) in [
+            (Pacing::Unpaced, 0, 50),
+            (Pacing::Recorded, 100, 1000),
+            (Pacing::Scaled(0.5), 200, 1000),
+        ] {
+            let channel = ActionChannel::new(16);
+            let consumer = {
+                let channel = channel.clone();
+                std::thread::spawn(move || channel.iter().count())
+            };
+            let started = Instant::now();
+            assert_eq!(replay(ActionsFileReader::new(&path).unwrap(), &channel, pacing).unwrap(), 3);
+            let elapsed = started.elapsed().as_millis();
+            assert!(elapsed >= min && elapsed < max, "{:?} took {} ms", pacing, elapsed);
+            assert_eq!(consumer.join().unwrap(), 3);
+        }
+        assert!(replay(ActionsFileReader::new(&path).unwrap(), &ActionChannel::new(16), Pacing::Scaled(0.0)).is_err());
+
+        let channel = ActionChannel::with_policy(1, OverflowPolicy::Timeout(Duration::from_millis(1)));
+        let consumer = {
+            let channel = channel.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                channel.iter().count()
+            })
+        };
+        assert_eq!(replay(ActionsFileReader::new(&path).unwrap(), &channel, Pacing::Unpaced).unwrap(), 1);
+        assert_eq!(consumer.join().unwrap(), 1);
+        assert_eq!(channel.metrics().dropped, 2);
+
+        let channel = ActionChannel::new(1);
+        let consumer = {
+            let channel = channel.clone();
+            std::thread::spawn(move || channel.iter().take(1).count())
+        };
+        assert!(replay(ActionsFileReader::new(&path).unwrap(), &channel, Pacing::Unpaced).is_err());
+        assert_eq!(consumer.join().unwrap(), 1);
+    }
+}
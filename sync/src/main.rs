@@ -0,0 +1,289 @@
+mod node;
+#[cfg(test)]
+mod mock;
+
+use std::time::Duration;
+
+use clap::{App, Arg};
+use futures::stream::{self, StreamExt};
+use io::channel::ContextActionMessage;
+use io::{ActionsFileWriter, Block};
+use anyhow::{anyhow, Result};
+
+use crate::node::{Node, RetryPolicy};
+
+#[tokio::main]
+async fn main() {
+    let matches = App::new("Tezedge Action Sync Tool")
+        .author("mambisi.zempare@simplestaking.com")
+        .arg(Arg::with_name("node")
+            .short("n")
+            .long("node")
+            .value_name("NODE")
+            .help("Node base url")
+            .default_value("http://127.0.0.1:18732")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("limit")
+            .short("l")
+            .long("limit")
+            .value_name("LIMIT")
+            .help("Set the number of block to sync from the current block")
+            .default_value("500000")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("file")
+            .short("f")
+            .long("file")
+            .value_name("FILE")
+            .help("output file path")
+            .default_value("./actions.bin")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("concurrency")
+            .short("c")
+            .long("concurrency")
+            .value_name("BLOCKS")
+            .help("Number of blocks fetched concurrently")
+            .default_value("8")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("retries")
+            .long("retries")
+            .value_name("RETRIES")
+            .help("Number of times a failed request is retried")
+            .default_value("5")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("timeout")
+            .long("timeout")
+            .value_name("MILLISECONDS")
+            .help("Time a request may take before it is failed and retried")
+            .default_value("30000")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("backoff")
+            .long("backoff")
+            .value_name("MILLISECONDS")
+            .help("Wait before the first retry, doubled on every further retry")
+            .default_value("100")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("rollback")
+            .short("r")
+            .long("rollback")
+            .help("Roll the file back to the last block on the node's chain if its head was reorganized away")
+        )
+        .get_matches();
+
+    let retry = RetryPolicy {
+        max_retries: matches.value_of("retries").unwrap().parse::<u32>().expect("Invalid retries"),
+        initial_backoff: Duration::from_millis(matches.value_of("backoff").unwrap().parse::<u64>().expect("Invalid backoff")),
+        ..RetryPolicy::default()
+    };
+    let timeout = Duration::from_millis(matches.value_of("timeout").unwrap().parse::<u64>().expect("Invalid timeout"));
+    let node = Node::new(matches.value_of("node").unwrap()).with_retry(retry).with_timeout(timeout);
+    let limit = matches.value_of("limit").unwrap().parse::<u32>().expect("Invalid limit");
+    let concurrency = matches.value_of("concurrency").unwrap().parse::<usize>().expect("Invalid concurrency");
+    let file = matches.value_of("file").unwrap();
+
+    let mut writer = ActionsFileWriter::new(file).unwrap();
+    let from_level = match resume_level(&node, &mut writer, matches.is_present("rollback")).await {
+        Ok(level) => level,
+        Err(e) => {
+            eprintln!("Cannot resume sync: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+    match sync_blocks(&node, &mut writer, from_level, limit, concurrency).await {
+        Ok(count) => {
+            println!("Synced {} blocks", count);
+        }
+        Err(e) => {
+            eprintln!("Sync failed: {:?}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Returns the level to continue syncing from, the block after the file's current head
+///
+/// If the node's chain no longer contains the head, the file is rolled back to the last block
+/// the node still has when `rollback` is set, and an error is returned otherwise.
+async fn resume_level(node: &Node, writer: &mut ActionsFileWriter, rollback: bool) -> Result<u32> {
+    let header = writer.header();
+    if header.block_count == 0 {
+        return Ok(1);
+    }
+    let head = match node.block(header.block_height).await? {
+        Some(head) => head,
+        None => {
+            return Err(anyhow!("Node has no block at the file's head level {}", header.block_height));
+        }
+    };
+    if head.block_hash == header.current_block_hash {
+        return Ok(header.block_height + 1);
+    }
+    if !rollback {
+        return Err(anyhow!(
+            "File head {} at level {} is not on the node's chain, rerun with --rollback to drop the reorganized blocks",
+            header.current_block_hash,
+            header.block_height
+        ));
+    }
+    for entry in writer.index()?.iter().rev() {
+        let level = entry.block.block_level;
+        match node.block(level).await? {
+            Some(block) if block.block_hash == entry.block.block_hash => {
+                println!("Rolling back to block {} at level {}", block.block_hash, level);
+                writer.rollback(level)?;
+                return Ok(level + 1);
+            }
+            _ => (),
+        }
+    }
+    Err(anyhow!("File shares no block with the node's chain"))
+}
+
+/// Appends up to `limit` blocks starting at `from_level` to `writer`
+///
+/// Up to `concurrency` blocks are fetched at once, and written strictly in level order.
+/// Stops early when the node has no block at the next level, returns the number of blocks written
+async fn sync_blocks(node: &Node, writer: &mut ActionsFileWriter, from_level: u32, limit: u32, concurrency: usize) -> Result<u32> {
+    let mut fetches = stream::iter(from_level..from_level.saturating_add(limit))
+        .map(|level| fetch_block(node, level))
+        .buffered(concurrency.max(1));
+    let mut count = 0;
+    while let Some(fetched) = fetches.next().await {
+        let (block, actions) = match fetched? {
+            Some(fetched) => fetched,
+            None => break,
+        };
+        println!("[{:<10}] {} {} actions", block.block_level, block.block_hash, actions.len());
+        writer.update(block, actions)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Fetches the block at `level` with its actions, `None` if the node does not have it yet
+async fn fetch_block(node: &Node, level: u32) -> Result<Option<(Block, Vec<ContextActionMessage>)>> {
+    let block = match node.block(level).await? {
+        Some(block) => block,
+        None => return Ok(None),
+    };
+    let actions = node.actions(&block.block_hash).await?;
+    Ok(Some((block, actions)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, MockNode};
+    use io::ActionsFileReader;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn fast_retry(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_from_mock_node() {
+        let mock = MockNode::chain(5);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("actions.bin");
+        let mut writer = ActionsFileWriter::new(&path).unwrap();
+
+        let count = sync_blocks(&Node::new(&mock.url), &mut writer, 1, 3, 2).await.unwrap();
+        assert_eq!(count, 3);
+        let count = sync_blocks(&Node::new(&mock.url), &mut writer, 4, 10, 4).await.unwrap();
+        assert_eq!(count, 2);
+
+        let blocks = ActionsFileReader::new(&path).unwrap().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(blocks.len(), 5);
+        for (level, (block, actions)) in (1..).zip(blocks) {
+            assert_eq!(block.block_hash, mock::block_hash(level));
+            assert_eq!(actions, mock::actions(level));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resume_after_reorg() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("actions.bin");
+        let mut writer = ActionsFileWriter::new(&path).unwrap();
+        let node = Node::new(&MockNode::chain(4).url);
+        sync_blocks(&node, &mut writer, 1, 4, 1).await.unwrap();
+        assert_eq!(resume_level(&node, &mut writer, false).await.unwrap(), 5);
+
+        let forked = Node::new(&MockNode::fork(6, 3).url);
+        assert!(resume_level(&forked, &mut writer, false).await.is_err());
+        assert_eq!(writer.header().block_count, 4);
+
+        let level = resume_level(&forked, &mut writer, true).await.unwrap();
+        assert_eq!(level, 3);
+        assert_eq!(writer.header().block_count, 2);
+        assert_eq!(writer.header().current_block_hash, mock::block_hash(2));
+        assert_eq!(sync_blocks(&forked, &mut writer, level, 10, 3).await.unwrap(), 4);
+
+        let blocks = ActionsFileReader::new(&path).unwrap().collect::<Result<Vec<_>>>().unwrap();
+        let hashes: Vec<_> = blocks.iter().map(|(block, _)| block.block_hash).collect();
+        let expected: Vec<_> = (1..=6).map(|level| mock::branch_hash(level, 3)).collect();
+        assert_eq!(hashes, expected);
+        assert_eq!(ActionsFileReader::new(&path).unwrap().header().actions_count, 6);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_sync_retries_failures() {
+        let requests = AtomicUsize::new(0);
+        let mock = MockNode::start(move |path| {
+            if requests.fetch_add(1, Ordering::SeqCst).is_multiple_of(3) {
+                return (503, String::new());
+            }
+            mock::chain_response(30, u32::MAX, path)
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("actions.bin");
+        let mut writer = ActionsFileWriter::new(&path).unwrap();
+        let node = Node::new(&mock.url).with_retry(fast_retry(5)).with_timeout(Duration::from_secs(5));
+
+        assert_eq!(sync_blocks(&node, &mut writer, 1, 100, 8).await.unwrap(), 30);
+        let levels: Vec<_> = ActionsFileReader::new(&path).unwrap().map(|item| item.unwrap().0.block_level).collect();
+        assert_eq!(levels, (1..=30).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_sync_retries_timed_out_requests() {
+        let requests = AtomicUsize::new(0);
+        let mock = MockNode::start(move |path| {
+            if requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                std::thread::sleep(Duration::from_millis(300));
+            }
+            mock::chain_response(3, u32::MAX, path)
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("actions.bin");
+        let mut writer = ActionsFileWriter::new(&path).unwrap();
+        let node = Node::new(&mock.url).with_retry(fast_retry(5)).with_timeout(Duration::from_millis(100));
+
+        assert_eq!(sync_blocks(&node, &mut writer, 1, 10, 1).await.unwrap(), 3);
+        assert_eq!(writer.header().block_count, 3);
+    }
+
+    #[tokio::test]
+    async fn test_sync_gives_up_after_retries() {
+        let mock = MockNode::start(|_| (500, String::new()));
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer = ActionsFileWriter::new(dir.path().join("actions.bin")).unwrap();
+        let node = Node::new(&mock.url).with_retry(fast_retry(2));
+
+        assert!(sync_blocks(&node, &mut writer, 1, 10, 4).await.is_err());
+        assert_eq!(writer.header().block_count, 0);
+        assert_eq!(RetryPolicy::default().backoff(3), Duration::from_millis(800));
+        assert_eq!(RetryPolicy::default().backoff(20), Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,97 @@
+//! Local HTTP server standing in for a tezedge node in tests
+
+use std::thread;
+
+use io::channel::{BlockHeaderJson, BlockJson, ContextAction, ContextActionJson, ContextActionMessage};
+use io::hash::BlockHash;
+
+pub struct MockNode {
+    pub url: String,
+}
+
+impl MockNode {
+    /// Serves every request with `handler(path)`, which returns the status code and body
+    pub fn start<F>(handler: F) -> Self
+        where F: Fn(&str) -> (u16, String) + Send + 'static {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let (status, body) = handler(request.url());
+                let response = tiny_http::Response::from_string(body).with_status_code(status);
+                let _ = request.respond(response);
+            }
+        });
+        MockNode {
+            url: format!("http://127.0.0.1:{}", port),
+        }
+    }
+
+    /// Serves a chain of `count` blocks starting at level 1, each with one checkout action
+    pub fn chain(count: u32) -> Self {
+        Self::start(move |path| chain_response(count, u32::MAX, path))
+    }
+
+    /// Like [`MockNode::chain`], but blocks from `fork_level` on are on another branch
+    pub fn fork(count: u32, fork_level: u32) -> Self {
+        Self::start(move |path| chain_response(count, fork_level, path))
+    }
+}
+
+pub fn block_hash(level: u32) -> BlockHash {
+    branch_hash(level, u32::MAX)
+}
+
+/// Hash of the block at `level` on the branch forking at `fork_level`
+pub fn branch_hash(level: u32, fork_level: u32) -> BlockHash {
+    let mut hash = [0_u8; 32];
+    hash[..4].copy_from_slice(&level.to_be_bytes());
+    if level >= fork_level {
+        hash[31] = 1;
+    }
+    BlockHash::new(hash)
+}
+
+pub fn actions(level: u32) -> Vec<ContextActionMessage> {
+    vec![ContextActionMessage {
+        action: ContextAction::Checkout {
+            context_hash: vec![level as u8; 32],
+            start_time: level as f64,
+            end_time: level as f64 + 0.5,
+        },
+        record: true,
+        perform: true,
+    }]
+}
+
+/// Canned response of a node holding blocks `1..=count`, forked at `fork_level`
+pub fn chain_response(count: u32, fork_level: u32, path: &str) -> (u16, String) {
+    if let Some(query) = path.strip_prefix("/dev/chains/main/blocks?") {
+        let level = query.split('&')
+            .find_map(|param| param.strip_prefix("from_block_id="))
+            .and_then(|level| level.parse::<u32>().ok())
+            .unwrap_or(0);
+        let blocks: Vec<_> = if level >= 1 && level <= count {
+            vec![BlockJson {
+                hash: branch_hash(level, fork_level),
+                header: BlockHeaderJson { level, predecessor: branch_hash(level - 1, fork_level) },
+            }]
+        } else {
+            vec![]
+        };
+        return (200, serde_json::to_string(&blocks).unwrap());
+    }
+    if let Some(hash) = path.strip_prefix("/dev/chains/main/actions/blocks/") {
+        let level = (1..=count).find(|level| branch_hash(*level, fork_level).to_string() == hash);
+        return match level {
+            Some(level) => {
+                let actions: Vec<_> = actions(level).into_iter()
+                    .map(|msg| ContextActionJson { action: msg.action })
+                    .collect();
+                (200, serde_json::to_string(&actions).unwrap())
+            }
+            None => (404, String::new()),
+        };
+    }
+    (404, String::new())
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use io::channel::{BlockJson, ContextActionJson, ContextActionMessage};
+use io::hash::BlockHash;
+use io::Block;
+
+/// How failed requests are retried
+///
+/// Connection errors, timeouts, `429` and `5xx` responses are retried up to `max_retries` times,
+/// waiting `initial_backoff` before the first retry and doubling the wait up to `max_backoff`.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before retry number `attempt`, starting at zero
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(attempt);
+        self.initial_backoff.checked_mul(factor).unwrap_or(self.max_backoff).min(self.max_backoff)
+    }
+}
+
+/// Time a request may take, from connecting until its body is read, before it is retried
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Client for the dev RPC of a tezedge node
+pub struct Node {
+    base_url: String,
+    client: reqwest::Client,
+    retry: RetryPolicy,
+}
+
+impl Node {
+    pub fn new(base_url: &str) -> Self {
+        Node {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: Self::client(DEFAULT_TIMEOUT),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    fn client(timeout: Duration) -> reqwest::Client {
+        reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("Failed to build HTTP client")
+    }
+
+    /// Fails and retries requests that take longer than `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.client = Self::client(timeout);
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Fetches the block at `level`, `None` if the node does not have it yet
+    pub async fn block(&self, level: u32) -> Result<Option<Block>> {
+        let url = format!("{}/dev/chains/main/blocks?limit=1&from_block_id={}", self.base_url, level);
+        let blocks = self.get::<Vec<BlockJson>>(&url).await?;
+        Ok(blocks.into_iter()
+            .find(|block| block.header.level == level)
+            .map(Block::from))
+    }
+
+    /// Fetches the actions recorded for block `hash`
+    pub async fn actions(&self, hash: &BlockHash) -> Result<Vec<ContextActionMessage>> {
+        let url = format!("{}/dev/chains/main/actions/blocks/{}", self.base_url, hash);
+        let actions = self.get::<Vec<ContextActionJson>>(&url).await?;
+        Ok(actions.into_iter().map(ContextActionMessage::from).collect())
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            let error = match self.client.get(url).send().await {
+                Ok(response) if response.status().is_success() => {
+                    match response.json::<T>().await {
+                        Ok(body) => return Ok(body),
+                        Err(e) if e.is_timeout() => anyhow!("GET {} failed: {}", url, e),
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let error = anyhow!("GET {} failed with status {}", url, status);
+                    if !status.is_server_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+                        return Err(error);
+                    }
+                    error
+                }
+                Err(e) => anyhow!("GET {} failed: {}", url, e),
+            };
+            if attempt >= self.retry.max_retries {
+                return Err(error.context(format!("giving up after {} retries", attempt)));
+            }
+            tokio::time::sleep(self.retry.backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+}